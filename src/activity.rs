@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::{Duration, Instant};
+
+/// Disk I/O per second of the process tree that counts as busy on its own.
+const BUSY_IO_BYTES_PER_SEC: u64 = 1024 * 1024;
+
+/// Samples no closer together than this are reused; the verdict needs some
+/// time to measure.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// CPU time and disk I/O of one process, from `/proc`.
+#[derive(Debug, Clone)]
+struct Usage {
+    name: String,
+    /// User and system time of the process and its waited-for children, in clock ticks
+    cpu_ticks: u64,
+    io_bytes: u64,
+}
+
+/// Watches the CPU time and disk I/O of the agent and everything it started,
+/// to tell a long build that prints nothing from a hung agent. Reads `/proc`,
+/// so elsewhere than on Linux the tree never counts as busy.
+#[derive(Debug, Default)]
+pub struct ProcessMonitor {
+    root: Option<u32>,
+    last: Option<(Instant, HashMap<u32, Usage>)>,
+    /// Names of the busy processes as of the last sample; empty if idle
+    busy: Vec<String>,
+    busy_since: Option<Instant>,
+}
+
+impl ProcessMonitor {
+    /// The processes of the tree under `root` that kept the CPU (at least
+    /// `cpu_percent` of one core) or the disk busy since the last sample; empty
+    /// if the tree is idle, or once it has been busy for longer than `max_busy`.
+    pub fn busy(&mut self, root: Option<u32>, cpu_percent: u32, max_busy: Duration) -> Vec<String> {
+        if root != self.root {
+            *self = Self { root, ..Self::default() };
+        }
+        let Some(root) = root else {
+            return Vec::new();
+        };
+        let now = Instant::now();
+        if self.last.as_ref().is_some_and(|(at, _)| now.duration_since(*at) < SAMPLE_INTERVAL) {
+            return self.current(max_busy);
+        }
+        let Some(sample) = sample_tree(root) else {
+            self.last = None;
+            self.busy.clear();
+            return Vec::new();
+        };
+        if let Some((at, previous)) = self.last.replace((now, sample.clone())) {
+            let seconds = now.duration_since(at).as_secs_f64();
+            match busy_processes(&previous, &sample, seconds, clock_ticks_per_sec(), cpu_percent) {
+                Some(names) => {
+                    self.busy = names;
+                    self.busy_since.get_or_insert(now);
+                }
+                None => {
+                    self.busy.clear();
+                    self.busy_since = None;
+                }
+            }
+        }
+        self.current(max_busy)
+    }
+
+    fn current(&self, max_busy: Duration) -> Vec<String> {
+        match self.busy_since {
+            Some(since) if since.elapsed() <= max_busy => self.busy.clone(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Names of the processes that did any work between `previous` and `sample`,
+/// taken `seconds` apart, busiest first; `None` if the tree as a whole used
+/// less than `cpu_percent` of one core and little disk I/O.
+fn busy_processes(previous: &HashMap<u32, Usage>, sample: &HashMap<u32, Usage>, seconds: f64,
+    ticks_per_sec: f64, cpu_percent: u32) -> Option<Vec<String>> {
+    let mut cpu_ticks = 0;
+    let mut io_bytes = 0;
+    let mut busiest: Vec<(u64, &str)> = Vec::new();
+    for (pid, usage) in sample {
+        // A process started since the last sample used all of its time since then
+        let (cpu, io) = match previous.get(pid) {
+            Some(before) => (usage.cpu_ticks.saturating_sub(before.cpu_ticks),
+                usage.io_bytes.saturating_sub(before.io_bytes)),
+            None => (usage.cpu_ticks, usage.io_bytes),
+        };
+        cpu_ticks += cpu;
+        io_bytes += io;
+        if cpu > 0 || io > 0 {
+            busiest.push((cpu, &usage.name));
+        }
+    }
+    let cpu_busy = cpu_ticks as f64 / ticks_per_sec / seconds * 100.0 >= f64::from(cpu_percent);
+    let io_busy = io_bytes as f64 / seconds >= BUSY_IO_BYTES_PER_SEC as f64;
+    if !cpu_busy && !io_busy {
+        return None;
+    }
+    busiest.sort_by_key(|(cpu, _)| std::cmp::Reverse(*cpu));
+    let mut names: Vec<String> = Vec::new();
+    for (_, name) in busiest {
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+    Some(names)
+}
+
+fn clock_ticks_per_sec() -> f64 {
+    // SAFETY: sysconf has no preconditions
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks > 0 { ticks as f64 } else { 100.0 }
+}
+
+/// Usage of `root` and all its descendants, or `None` if `root` can't be read.
+fn sample_tree(root: u32) -> Option<HashMap<u32, Usage>> {
+    let mut processes = HashMap::new();
+    for entry in fs::read_dir("/proc").ok()?.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse::<u32>().ok()) else {
+            continue;
+        };
+        let Some(stat) = fs::read_to_string(format!("/proc/{}/stat", pid)).ok().and_then(|stat| parse_stat(&stat)) else {
+            continue;
+        };
+        processes.insert(pid, stat);
+    }
+    let tree = descendants(root, processes)?;
+    Some(tree.into_iter()
+        .map(|(pid, (name, cpu_ticks))| {
+            let io_bytes = fs::read_to_string(format!("/proc/{}/io", pid)).map_or(0, |io| parse_io_bytes(&io));
+            (pid, Usage { name, cpu_ticks, io_bytes })
+        })
+        .collect())
+}
+
+/// Name and CPU time of `root` and all its descendants, out of `processes`
+/// (name, parent and CPU time by pid), or `None` if `root` is not among them.
+fn descendants(root: u32, mut processes: HashMap<u32, (String, u32, u64)>) -> Option<HashMap<u32, (String, u64)>> {
+    processes.get(&root)?;
+    let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (pid, (_, parent, _)) in &processes {
+        children.entry(*parent).or_default().push(*pid);
+    }
+
+    let mut tree = HashMap::new();
+    let mut pending = vec![root];
+    while let Some(pid) = pending.pop() {
+        if let Some((name, _, cpu_ticks)) = processes.remove(&pid) {
+            tree.insert(pid, (name, cpu_ticks));
+        }
+        pending.extend(children.get(&pid).into_iter().flatten());
+    }
+    Some(tree)
+}
+
+/// Name, parent and CPU time (utime + stime + cutime + cstime) from the
+/// contents of `/proc/<pid>/stat`.
+fn parse_stat(stat: &str) -> Option<(String, u32, u64)> {
+    // The name is in parentheses and may itself contain spaces or parentheses
+    let (start, end) = (stat.find('(')?, stat.rfind(')')?);
+    let name = stat.get(start + 1..end)?.to_string();
+    let fields: Vec<&str> = stat[end + 1..].split_whitespace().collect();
+    // fields[0] is the state (field 3 of stat), so field n is fields[n - 3]
+    let parent = fields.get(1)?.parse().ok()?;
+    let cpu_ticks = fields.get(11..15)?.iter().filter_map(|f| f.parse::<u64>().ok()).sum();
+    Some((name, parent, cpu_ticks))
+}
+
+/// Bytes read from and written to storage, from the contents of `/proc/<pid>/io`.
+fn parse_io_bytes(io: &str) -> u64 {
+    io.lines()
+        .filter_map(|line| line.split_once(": "))
+        .filter(|(key, _)| matches!(*key, "read_bytes" | "write_bytes"))
+        .filter_map(|(_, value)| value.trim().parse::<u64>().ok())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stat(name: &str, parent: u32, times: [u64; 4]) -> String {
+        format!("4242 ({}) S {} 4242 4242 0 -1 4194560 1234 0 0 0 {} {} {} {} 20 0 1 0 100 0 0",
+            name, parent, times[0], times[1], times[2], times[3])
+    }
+
+    fn usage(name: &str, cpu_ticks: u64, io_bytes: u64) -> Usage {
+        Usage { name: name.to_string(), cpu_ticks, io_bytes }
+    }
+
+    #[test]
+    fn parses_stat_lines() {
+        assert_eq!(parse_stat(&stat("cargo", 17, [10, 5, 2, 1])), Some(("cargo".to_string(), 17, 18)));
+        assert_eq!(parse_stat(&stat("(a) b)", 1, [3, 0, 0, 0])), Some(("(a) b)".to_string(), 1, 3)));
+        assert_eq!(parse_stat(&stat("tmux: server", 1, [0, 0, 0, 0])), Some(("tmux: server".to_string(), 1, 0)));
+        assert_eq!(parse_stat("4242 (cut) S 1 4242"), None);
+        assert_eq!(parse_stat("4242 cargo S 1"), None);
+    }
+
+    #[test]
+    fn parses_io_counters() {
+        let io = "rchar: 900\nwchar: 800\nsyscr: 7\nsyscw: 6\nread_bytes: 4096\nwrite_bytes: 8192\ncancelled_write_bytes: 0\n";
+        assert_eq!(parse_io_bytes(io), 12288);
+        assert_eq!(parse_io_bytes(""), 0);
+    }
+
+    #[test]
+    fn walks_the_process_tree_under_the_root() {
+        let processes = HashMap::from([
+            (1, ("init".to_string(), 0, 1)),
+            (10, ("claude".to_string(), 1, 2)),
+            (11, ("cargo".to_string(), 10, 3)),
+            (12, ("rustc".to_string(), 11, 4)),
+            (20, ("other".to_string(), 1, 5)),
+        ]);
+        let tree = descendants(10, processes.clone()).unwrap();
+        let mut pids: Vec<u32> = tree.keys().copied().collect();
+        pids.sort();
+        assert_eq!(pids, [10, 11, 12]);
+        assert_eq!(tree[&12], ("rustc".to_string(), 4));
+        assert_eq!(descendants(99, processes), None);
+    }
+
+    #[test]
+    fn busy_when_the_tree_uses_the_cpu_or_the_disk() {
+        let previous = HashMap::from([(10, usage("claude", 100, 0)), (11, usage("cargo", 50, 0))]);
+
+        // 150 ticks over 10s at 100 ticks/s is 15% of a core
+        let sample = HashMap::from([(10, usage("claude", 110, 0)), (11, usage("cargo", 190, 0))]);
+        assert_eq!(busy_processes(&previous, &sample, 10.0, 100.0, 10), Some(vec!["cargo".to_string(), "claude".to_string()]));
+        assert_eq!(busy_processes(&previous, &sample, 10.0, 100.0, 20), None);
+
+        // A new process counts with all of its time; one that did nothing is left out
+        let sample = HashMap::from([(10, usage("claude", 100, 0)), (11, usage("cargo", 50, 0)), (12, usage("rustc", 300, 0))]);
+        assert_eq!(busy_processes(&previous, &sample, 10.0, 100.0, 25), Some(vec!["rustc".to_string()]));
+
+        // Disk I/O alone is enough
+        let sample = HashMap::from([(10, usage("claude", 100, 0)), (11, usage("cargo", 50, 20 * BUSY_IO_BYTES_PER_SEC))]);
+        assert_eq!(busy_processes(&previous, &sample, 10.0, 100.0, 50), Some(vec!["cargo".to_string()]));
+        assert_eq!(busy_processes(&previous, &previous, 10.0, 100.0, 1), None);
+    }
+}
@@ -0,0 +1,360 @@
+use anyhow::{Context, Result};
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader, IsTerminal, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::claudia::key_bytes;
+use crate::debug_enabled;
+
+/// File name of the attach socket inside a run directory.
+pub const SOCKET_NAME: &str = "attach.sock";
+
+/// Raw output kept so a new viewer sees the current screen, not a blank one.
+const SCROLLBACK_BYTES: usize = 64 * 1024;
+
+/// First line sent by a client after connecting.
+#[derive(Debug, Serialize, Deserialize)]
+struct Hello {
+    read_only: bool,
+}
+
+/// Reply to [`Hello`]; on success the connection then carries raw terminal output.
+#[derive(Debug, Serialize, Deserialize)]
+struct Welcome {
+    ok: bool,
+    message: String,
+}
+
+/// Chunks of output queued for a client; one that falls further behind is dropped.
+const CLIENT_QUEUE: usize = 256;
+
+/// How long writing to a client may block before it is dropped.
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Clients mirroring Claude's screen.
+#[derive(Default)]
+pub struct Viewers {
+    screen: Mutex<Screen>,
+    /// Whether a client that forwards input is attached; only one may be
+    input_attached: Mutex<bool>,
+}
+
+/// The attached clients and the output a new one is sent first. Kept under
+/// one lock so a new client neither misses nor repeats output.
+#[derive(Default)]
+struct Screen {
+    clients: Vec<Client>,
+    scrollback: Vec<u8>,
+}
+
+struct Client {
+    /// Served by a writer thread, so a client that doesn't read never holds
+    /// up the PTY output thread
+    queue: mpsc::SyncSender<Vec<u8>>,
+    /// To hang up on the client
+    stream: UnixStream,
+}
+
+impl Client {
+    fn hang_up(&self) {
+        self.stream.shutdown(Shutdown::Both).ok();
+    }
+}
+
+impl Viewers {
+    /// Send output from the PTY to every attached client. Never blocks.
+    pub fn broadcast(&self, bytes: &[u8]) {
+        let mut screen = self.screen.lock().unwrap();
+        screen.scrollback.extend_from_slice(bytes);
+        let excess = screen.scrollback.len().saturating_sub(SCROLLBACK_BYTES);
+        screen.scrollback.drain(..excess);
+        // Drop clients that went away or fell behind
+        screen.clients.retain(|client| {
+            let sent = client.queue.try_send(bytes.to_vec()).is_ok();
+            if !sent {
+                client.hang_up();
+            }
+            sent
+        });
+    }
+
+    /// Hang up on every client, which ends their `claudia attach`.
+    fn close(&self) {
+        for client in std::mem::take(&mut self.screen.lock().unwrap().clients) {
+            client.hang_up();
+        }
+    }
+
+    fn add(&self, stream: UnixStream) -> Result<()> {
+        stream.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT))?;
+        let client_stream = stream.try_clone()?;
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(CLIENT_QUEUE);
+        thread::spawn(move || {
+            let mut stream = stream;
+            // Ends when the client is dropped or a write fails or times out
+            for bytes in receiver {
+                if stream.write_all(&bytes).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut screen = self.screen.lock().unwrap();
+        sender.try_send(screen.scrollback.clone()).ok();
+        screen.clients.push(Client { queue: sender, stream: client_stream });
+        Ok(())
+    }
+}
+
+/// Serves the attach socket of a run until dropped.
+pub struct AttachServer {
+    path: PathBuf,
+    viewers: Arc<Viewers>,
+    shutdown: Arc<Mutex<bool>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl AttachServer {
+    pub fn start(run_dir: &Path, viewers: Arc<Viewers>, input: mpsc::Sender<Vec<u8>>) -> Result<Self> {
+        let path = run_dir.join(SOCKET_NAME);
+        fs::remove_file(&path).ok();
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("Failed to create attach socket {}", path.display()))?;
+        listener.set_nonblocking(true)?;
+
+        let shutdown = Arc::new(Mutex::new(false));
+        let shutdown_clone = Arc::clone(&shutdown);
+        let server_viewers = Arc::clone(&viewers);
+        let thread = thread::spawn(move || {
+            while !*shutdown_clone.lock().unwrap() {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let viewers = Arc::clone(&server_viewers);
+                        let input = input.clone();
+                        // Each client gets its own thread since it stays connected
+                        thread::spawn(move || {
+                            if let Err(e) = serve(stream, &viewers, input) {
+                                if debug_enabled() {
+                                    eprintln!("[DEBUG] Attach connection failed: {}", e);
+                                }
+                            }
+                        });
+                    }
+                    Err(_) => thread::sleep(Duration::from_millis(100)),
+                }
+            }
+        });
+
+        Ok(Self { path, viewers, shutdown, thread: Some(thread) })
+    }
+}
+
+impl Drop for AttachServer {
+    fn drop(&mut self) {
+        *self.shutdown.lock().unwrap() = true;
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+        // Clients still attached would otherwise wait for output forever
+        self.viewers.close();
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+fn serve(stream: UnixStream, viewers: &Viewers, input: mpsc::Sender<Vec<u8>>) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let hello: Hello = serde_json::from_str(&line).context("Invalid attach request")?;
+
+    let mut stream = stream;
+    if !hello.read_only {
+        let mut input_attached = viewers.input_attached.lock().unwrap();
+        if *input_attached {
+            let welcome = Welcome {
+                ok: false,
+                message: "Another client is already attached with input; use --read-only".to_string(),
+            };
+            writeln!(stream, "{}", serde_json::to_string(&welcome)?)?;
+            return Ok(());
+        }
+        *input_attached = true;
+    }
+
+    let message = if hello.read_only { "Attached read-only" } else { "Attached" };
+    writeln!(stream, "{}", serde_json::to_string(&Welcome { ok: true, message: message.to_string() })?)?;
+    viewers.add(stream.try_clone()?)?;
+    if hello.read_only {
+        return Ok(());
+    }
+
+    // Forward keystrokes until the client detaches
+    stream.set_read_timeout(None)?;
+    let mut buf = [0u8; 1024];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                if input.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    *viewers.input_attached.lock().unwrap() = false;
+    Ok(())
+}
+
+/// `claudia attach`: mirror the screen of a run and forward keystrokes to it.
+/// Ctrl+] (or Ctrl+C) detaches and leaves the run going.
+pub fn attach(run_dir: &Path, read_only: bool) -> Result<i32> {
+    let path = run_dir.join(SOCKET_NAME);
+    let mut stream = UnixStream::connect(&path)
+        .with_context(|| format!("Failed to connect to {} (is the run still active?)", path.display()))?;
+    writeln!(stream, "{}", serde_json::to_string(&Hello { read_only })?)?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let welcome: Welcome = serde_json::from_str(&line).context("Invalid response from the run")?;
+    if !welcome.ok {
+        eprintln!("{}", welcome.message);
+        return Ok(1);
+    }
+    println!("{}. Press Ctrl+] to detach.\r", welcome.message);
+
+    let is_tty = std::io::stdin().is_terminal();
+    if is_tty {
+        enable_raw_mode().context("Failed to enable raw mode")?;
+    }
+
+    let finished = Arc::new(Mutex::new(false));
+    let finished_clone = Arc::clone(&finished);
+    let output_thread = thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        let mut stdout = std::io::stdout();
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    stdout.write_all(&buf[..n]).ok();
+                    stdout.flush().ok();
+                }
+            }
+        }
+        *finished_clone.lock().unwrap() = true;
+    });
+
+    let mut detached = false;
+    while !*finished.lock().unwrap() {
+        if !is_tty {
+            thread::sleep(Duration::from_millis(100));
+            continue;
+        }
+        if !event::poll(Duration::from_millis(50)).unwrap_or(false) {
+            continue;
+        }
+        let Ok(Event::Key(key_event)) = event::read() else {
+            continue;
+        };
+        if key_event.modifiers.contains(KeyModifiers::CONTROL)
+            && matches!(key_event.code, KeyCode::Char(']') | KeyCode::Char('5') | KeyCode::Char('c'))
+        {
+            detached = true;
+            break;
+        }
+        let bytes = key_bytes(key_event.code);
+        if !read_only && !bytes.is_empty() && stream.write_all(&bytes).is_err() {
+            break;
+        }
+    }
+
+    stream.shutdown(std::net::Shutdown::Both).ok();
+    output_thread.join().ok();
+    if is_tty {
+        disable_raw_mode().ok();
+    }
+    if detached {
+        println!("\nDetached; the run keeps going.");
+    } else {
+        println!("\nThe run has ended.");
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn client_that_never_reads_does_not_block_output() {
+        let viewers = Viewers::default();
+        let (ours, _theirs) = UnixStream::pair().unwrap();
+        viewers.add(ours).unwrap();
+        let started = Instant::now();
+        let chunk = vec![b'x'; 4096];
+        for _ in 0..(CLIENT_QUEUE * 4) {
+            viewers.broadcast(&chunk);
+        }
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(viewers.screen.lock().unwrap().clients.is_empty());
+    }
+
+    #[test]
+    fn new_client_gets_scrollback_then_output() {
+        let viewers = Viewers::default();
+        viewers.broadcast(b"before ");
+        let (ours, mut theirs) = UnixStream::pair().unwrap();
+        viewers.add(ours).unwrap();
+        viewers.broadcast(b"after");
+        theirs.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut received = Vec::new();
+        let mut buf = [0u8; 64];
+        while received.len() < b"before after".len() {
+            let n = theirs.read(&mut buf).unwrap();
+            received.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(received, b"before after");
+    }
+
+    #[test]
+    fn dropping_the_server_hangs_up_on_clients() {
+        let run_dir = std::env::temp_dir().join(format!("claudia-attach-test-{}", std::process::id()));
+        fs::create_dir_all(&run_dir).unwrap();
+        let (input, _keys) = mpsc::channel();
+        let server = AttachServer::start(&run_dir, Arc::new(Viewers::default()), input).unwrap();
+
+        let mut clients = Vec::new();
+        for read_only in [false, true] {
+            let mut stream = UnixStream::connect(run_dir.join(SOCKET_NAME)).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            writeln!(stream, "{}", serde_json::to_string(&Hello { read_only }).unwrap()).unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert!(line.contains("\"ok\":true"), "{}", line);
+            clients.push(reader);
+        }
+        // Wait until both clients are set up
+        while server.viewers.screen.lock().unwrap().clients.len() < 2 {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        drop(server);
+        for mut client in clients {
+            let mut buf = [0u8; 64];
+            assert_eq!(client.read(&mut buf).unwrap(), 0);
+        }
+        fs::remove_dir_all(&run_dir).ok();
+    }
+}
@@ -0,0 +1,1758 @@
+use anyhow::{Result, Context};
+use regex::Regex;
+use std::io::{Read, Write, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Local, NaiveTime};
+use portable_pty::{native_pty_system, Child, CommandBuilder, MasterPty, PtySize};
+use std::fs;
+use crossterm::{
+    terminal::{enable_raw_mode, disable_raw_mode},
+    event::{self, Event, KeyCode, KeyModifiers},
+};
+
+use crate::activity::ProcessMonitor;
+use crate::attach::{AttachServer, Viewers};
+use crate::control::{Control, ControlServer};
+use crate::hooks::{self, Hook, HookContext};
+use crate::notify::{Notice, Notifier, Payload};
+use crate::loops;
+use crate::plan::{self, Plan};
+use crate::progress::{self, Escalation, Snapshot, StallTracker};
+use crate::schedule::{self, WaitReason};
+use crate::settings::{self, EscalationStep, Settings, SuspiciousPolicy};
+use crate::state::{self, EventKind, Phase, RunRecord};
+use crate::tasks::{self, Task};
+use crate::template::{Template, Vars};
+use crate::{debug_enabled, report, ConfigError, ExitReason, RunSummary};
+
+/// A session that exits sooner than this after starting counts as a quick exit
+/// for the restart backoff.
+const QUICK_EXIT: Duration = Duration::from_secs(120);
+
+/// Longest wait before restarting Claude.
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+/// The initial prompt used unless `prompt_template` or `prompt_template_file` is set.
+const DEFAULT_PROMPT_TEMPLATE: &str = "\
+Please read and complete all tasks in the file: {{file_name}}
+The file is located at: {{file_path}}
+Work through each task and:
+1. Complete the task as described
+2. Edit the markdown file to change [ ] to [x] for each completed task
+{{#if plan_files}}
+The plan continues in these files, which are part of the same work: {{plan_files}}
+Tasks in included files count as tasks of the file that includes them.
+A task that links to another Markdown file is done only when every task in that file is done: \
+work through the linked file's tasks, marking them [x] there.
+{{/if}}
+{{#if rules}}
+Also follow these rules:
+{{rules}}
+{{/if}}
+";
+
+pub struct Claudia {
+    md_file: PathBuf,
+    settings: Settings,
+    record: Arc<RunRecord>,
+    control: Arc<Control>,
+    output_buffer: Arc<Mutex<String>>,
+    last_output_time: Arc<Mutex<Instant>>,
+    continue_count: Arc<Mutex<u32>>,
+    status: Arc<Mutex<String>>,
+    response_history: Arc<Mutex<Vec<String>>>,
+    interrupted: Arc<Mutex<bool>>,
+    started_at: Instant,
+    initial_completed: Arc<Mutex<usize>>,
+    known_tasks: Arc<Mutex<Vec<Task>>>,
+    /// Task file content as of the last look, to spot edits
+    last_content: Arc<Mutex<String>>,
+    /// Settings with the overrides of the current task, as of the last change to the task file
+    task_settings: Arc<Mutex<Settings>>,
+    /// Tasks added while running, announced to Claude with the next Continue
+    new_tasks: Arc<Mutex<Vec<String>>>,
+    /// The task file with its included and linked files, once loaded
+    plan: Arc<Mutex<Option<Plan>>>,
+    last_task_check: Arc<Mutex<Instant>>,
+    /// When the run has to end (`--until` / `--max-duration`)
+    deadline: Option<DateTime<Local>>,
+    wrap_up_sent: Arc<Mutex<bool>>,
+    /// The current task when Claude was last prompted, for `task_prompt`
+    prompted_task: Arc<Mutex<Option<String>>>,
+    /// End of the output of the last failed verification
+    last_failure: Arc<Mutex<String>>,
+    /// Where the escalation ladder stands
+    escalation: Arc<Mutex<Escalation>>,
+    notifier: Arc<Notifier>,
+    /// Changes to the tasks undone by the guard, to tell Claude about
+    guard_notes: Arc<Mutex<Vec<String>>>,
+    /// The task `on_task_start` last ran for
+    started_task: Arc<Mutex<Option<String>>>,
+    /// Whether Claude was seen using a tool since it was last prompted
+    tool_activity: Arc<Mutex<bool>>,
+    /// Tool calls on the task file in the output buffer at the last look
+    task_file_calls: Arc<Mutex<usize>>,
+    /// Whether Claude was seen editing the task file since it was last re-read;
+    /// edits without such a call are the operator's and aren't guarded
+    claude_edited_tasks: Arc<Mutex<bool>>,
+    /// Working tree fingerprint as of the last completed task
+    tree_mark: Arc<Mutex<Option<u64>>>,
+    /// Tasks reopened as suspicious; each is challenged only once
+    challenged: Arc<Mutex<Vec<String>>>,
+    /// Challenges for reopened tasks, sent with the next Continue
+    challenges: Arc<Mutex<Vec<String>>>,
+    /// Suspicious completions waiting for the verify command, with why they are suspect
+    verify_queue: Arc<Mutex<Vec<(Task, String)>>>,
+    /// The verify command running in the background for suspicious completions
+    verification: Arc<Mutex<Option<Verification>>>,
+    /// Continues sent without file changes or task progress
+    stall: Arc<Mutex<StallTracker>>,
+    /// CPU and disk use of Claude's process tree
+    processes: Arc<Mutex<ProcessMonitor>>,
+    /// Whether a Continue is being held off because the processes are busy
+    waiting_on_processes: Arc<Mutex<bool>>,
+}
+
+/// The agent process in its PTY, with the thread copying its output.
+struct Session {
+    child: Box<dyn Child + Send + Sync>,
+    writer: Box<dyn Write + Send>,
+    // Keep the master side open for as long as the agent runs
+    _master: Box<dyn MasterPty + Send>,
+    output_thread: thread::JoinHandle<()>,
+    started: Instant,
+}
+
+/// A run of the verify command for a batch of suspicious completions.
+struct Verification {
+    tasks: Vec<(Task, String)>,
+    /// Returns the failure message, if the command failed
+    run: thread::JoinHandle<Option<String>>,
+}
+
+impl Session {
+    /// Wait for the output thread, which ends once the agent has exited.
+    fn close(self) {
+        drop(self.writer);
+        self.output_thread.join().ok();
+    }
+}
+
+impl Claudia {
+    pub fn new(
+        md_file: PathBuf,
+        settings: Settings,
+        record: RunRecord,
+        deadline: Option<DateTime<Local>>,
+        interrupted: Arc<Mutex<bool>>,
+    ) -> Self {
+        let record = Arc::new(record);
+        Self {
+            md_file,
+            notifier: Arc::new(Notifier::new(&settings, record.clone())),
+            task_settings: Arc::new(Mutex::new(settings.clone())),
+            settings,
+            record,
+            control: Arc::new(Control::default()),
+            output_buffer: Arc::new(Mutex::new(String::new())),
+            last_output_time: Arc::new(Mutex::new(Instant::now())),
+            continue_count: Arc::new(Mutex::new(0)),
+            status: Arc::new(Mutex::new("Starting...".to_string())),
+            response_history: Arc::new(Mutex::new(Vec::new())),
+            interrupted,
+            started_at: Instant::now(),
+            initial_completed: Arc::new(Mutex::new(0)),
+            known_tasks: Arc::new(Mutex::new(Vec::new())),
+            last_content: Arc::new(Mutex::new(String::new())),
+            new_tasks: Arc::new(Mutex::new(Vec::new())),
+            plan: Arc::new(Mutex::new(None)),
+            last_task_check: Arc::new(Mutex::new(Instant::now())),
+            deadline,
+            wrap_up_sent: Arc::new(Mutex::new(false)),
+            prompted_task: Arc::new(Mutex::new(None)),
+            last_failure: Arc::new(Mutex::new(String::new())),
+            escalation: Arc::new(Mutex::new(Escalation::default())),
+            guard_notes: Arc::new(Mutex::new(Vec::new())),
+            started_task: Arc::new(Mutex::new(None)),
+            tool_activity: Arc::new(Mutex::new(false)),
+            task_file_calls: Arc::new(Mutex::new(0)),
+            claude_edited_tasks: Arc::new(Mutex::new(false)),
+            tree_mark: Arc::new(Mutex::new(None)),
+            challenged: Arc::new(Mutex::new(Vec::new())),
+            challenges: Arc::new(Mutex::new(Vec::new())),
+            verify_queue: Arc::new(Mutex::new(Vec::new())),
+            verification: Arc::new(Mutex::new(None)),
+            stall: Arc::new(Mutex::new(StallTracker::default())),
+            processes: Arc::new(Mutex::new(ProcessMonitor::default())),
+            waiting_on_processes: Arc::new(Mutex::new(false)),
+        }
+    }
+    
+    // Helper function to safely get last N chars from a string
+    fn safe_suffix(s: &str, max_chars: usize) -> &str {
+        let char_count = s.chars().count();
+        if char_count <= max_chars {
+            return s;
+        }
+        
+        let skip_chars = char_count - max_chars;
+        let mut char_indices = s.char_indices();
+        
+        // Skip to the desired starting position
+        for _ in 0..skip_chars {
+            char_indices.next();
+        }
+        
+        // Get the byte index of the start position
+        if let Some((byte_idx, _)) = char_indices.next() {
+            &s[byte_idx..]
+        } else {
+            s
+        }
+    }
+
+    /// The template for the initial prompt: from `prompt_template_file`,
+    /// `prompt_template` or the built-in one.
+    pub fn prompt_template(md_file: &Path, settings: &Settings) -> Result<Template, ConfigError> {
+        if let Some(path) = settings.prompt_template_path(md_file) {
+            let text = fs::read_to_string(&path).map_err(|e| {
+                ConfigError(format!("Failed to read prompt template {}: {}", path.display(), e))
+            })?;
+            return Template::parse(&path.display().to_string(), &text);
+        }
+        match settings.prompt_template.as_str() {
+            "" => Template::parse("the built-in prompt", DEFAULT_PROMPT_TEMPLATE),
+            text => Template::parse("prompt_template", text),
+        }
+    }
+
+    /// Template variables describing the task file as it is now. Tasks for
+    /// which `is_done` is false count as open.
+    pub fn prompt_vars(md_file: &Path, settings: &Settings, run_id: &str, is_done: &dyn Fn(&Task) -> bool) -> Vars {
+        let content = fs::read_to_string(md_file).unwrap_or_default();
+        let content = tasks::normalize_checkboxes(&content).unwrap_or(content);
+        let all_tasks = tasks::parse_tasks(&content);
+        let list = |tasks: &mut dyn Iterator<Item = &Task>| -> String {
+            tasks.map(|t| format!("- [{}] {}", if t.checked { "x" } else { " " }, t.text))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        let open: Vec<&Task> = all_tasks.iter().filter(|t| !is_done(t)).collect();
+        let current = open.first().copied();
+        let plan = Plan::load(md_file).ok();
+        let (completed, total) = plan.as_ref().map_or_else(
+            || (all_tasks.iter().filter(|t| t.checked).count(), all_tasks.len()),
+            |plan| plan.counts(&|t| t.checked),
+        );
+        let plan_files: Vec<String> = plan.iter()
+            .flat_map(|plan| plan.sub_files().iter().map(|f| f.path.display().to_string()))
+            .collect();
+        // Relative to claudia's directory, so give the full path when Claude runs elsewhere
+        let file_path = if settings.working_dir.is_empty() {
+            md_file.to_path_buf()
+        } else {
+            md_file.canonicalize().unwrap_or_else(|_| md_file.to_path_buf())
+        };
+
+        let mut vars = Vars::new();
+        vars.insert("file_name", md_file.file_name().unwrap_or_default().to_string_lossy().into_owned());
+        vars.insert("file_path", file_path.display().to_string());
+        vars.insert("working_dir", settings.working_dir(md_file).display().to_string());
+        vars.insert("run_id", run_id.to_string());
+        vars.insert("tasks", list(&mut all_tasks.iter()));
+        vars.insert("remaining_tasks", list(&mut open.iter().copied()));
+        vars.insert("next_tasks", open.iter().take(3).map(|t| t.text.as_str()).collect::<Vec<_>>().join("; "));
+        vars.insert("total_count", total.to_string());
+        vars.insert("completed_count", completed.to_string());
+        vars.insert("remaining_count", (total - completed).to_string());
+        vars.insert("current_task", current.map(|t| t.text.clone()).unwrap_or_default());
+        vars.insert("section", current.and_then(|t| tasks::section(&content, t.line)).unwrap_or_default());
+        vars.insert("plan_files", plan_files.join(", "));
+        vars.insert("rules", settings.rules.iter().map(|r| format!("- {}", r)).collect::<Vec<_>>().join("\n"));
+        vars.insert("previous_failure", String::new());
+        vars.insert("failed_task", String::new());
+        vars
+    }
+
+    pub fn create_initial_prompt(md_file: &Path, settings: &Settings, run_id: &str) -> Result<String, ConfigError> {
+        let template = Self::prompt_template(md_file, settings)?;
+        Ok(template.render(&Self::prompt_vars(md_file, settings, run_id, &|t| t.checked)))
+    }
+
+    /// Type a message into Claude's prompt and submit it.
+    fn send_message(&self, writer: &mut dyn Write, message: &str) -> Result<()> {
+        *self.tool_activity.lock().unwrap() = false;
+        *self.waiting_on_processes.lock().unwrap() = false;
+        write!(writer, "{}", message)?;
+        writer.flush()?;
+        thread::sleep(Duration::from_millis(50));
+        writer.write_all(&[0x0D])?; // CR (Enter key)
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Fill in a message template from the settings with the run's current state.
+    fn render(&self, name: &str, text: &str) -> String {
+        // Templates were checked when the settings were validated
+        Template::parse(name, text).map_or_else(|_| text.to_string(), |template| template.render(&self.vars()))
+    }
+
+    /// Template variables for the run as it is now.
+    fn vars(&self) -> Vars {
+        let mut vars = Self::prompt_vars(&self.md_file, &self.settings, &self.record.run_id(), &|t| self.is_task_done(t));
+        vars.insert("previous_failure", self.last_failure.lock().unwrap().clone());
+        vars.insert("failed_task", self.control.failed.lock().unwrap().last().cloned().unwrap_or_default());
+        vars
+    }
+
+    /// Tell the webhooks about `notice`. `task` is the task it concerns.
+    fn notify(&self, notice: Notice, task: Option<String>, summary: &str, exit_code: Option<i32>) {
+        let (completed, total) = self.count_tasks();
+        let payload = Payload {
+            event: notice.name(),
+            run_id: self.record.run_id(),
+            task_file: self.md_file.display().to_string(),
+            task,
+            summary: summary.to_string(),
+            tasks_total: total,
+            tasks_completed: completed,
+            continues: *self.continue_count.lock().unwrap(),
+            exit_code,
+        };
+        let mut vars = self.vars();
+        vars.insert("event", notice.name().to_string());
+        vars.insert("summary", summary.to_string());
+        self.notifier.send(notice, &payload, &vars);
+    }
+
+    /// Run the command configured for `hook`, if any. A failing hook is
+    /// reported but doesn't change the course of the run.
+    fn run_hook(&self, hook: Hook, task: Option<Task>, reason: Option<ExitReason>) {
+        let command = hook.command(&self.settings.hooks);
+        if command.trim().is_empty() {
+            return;
+        }
+        let (completed, total) = self.count_tasks();
+        let working_dir = self.settings.working_dir(&self.md_file);
+        let context = HookContext {
+            hook: hook.name(),
+            run_id: self.record.run_id(),
+            task_file: self.md_file.display().to_string(),
+            working_dir: working_dir.display().to_string(),
+            task_id: task.as_ref().map(|t| t.id),
+            task: task.map(|t| t.text),
+            continues: *self.continue_count.lock().unwrap(),
+            tasks_total: total,
+            tasks_completed: completed,
+            exit_reason: reason,
+            exit_code: reason.map(|r| r.code()),
+        };
+        // The hook runs elsewhere, so it needs the full path
+        let dir = std::path::absolute(self.record.dir()).unwrap_or_else(|_| self.record.dir().to_path_buf());
+        let result = hooks::run(command, &context, &working_dir, &dir.join("hook.json"), &dir.join("hooks.log"),
+            self.settings.hook_timeout);
+        if let Err(e) = result {
+            eprintln!("[WARN] Hook {} failed: {:#}", hook.name(), e);
+            self.record.event(EventKind::HookFailed { hook: hook.name().to_string(), error: format!("{:#}", e) });
+        }
+    }
+
+    /// The initial prompt for a new session.
+    fn initial_prompt(&self) -> Result<String> {
+        Ok(Self::prompt_template(&self.md_file, &self.settings)?.render(&self.vars()))
+    }
+
+    /// Replace the session with a new Claude and send it the initial prompt.
+    fn restart_session(&self, session: &mut Session, working_dir: &Path, viewers: &Arc<Viewers>) -> Result<()> {
+        self.respawn(session, working_dir, viewers, &[])?;
+        let prompt = self.initial_prompt()?;
+        self.send_initial_prompt(session, &prompt)
+    }
+
+    /// Replace the session with a new Claude started with `extra_args`.
+    fn respawn(&self, session: &mut Session, working_dir: &Path, viewers: &Arc<Viewers>, extra_args: &[&str]) -> Result<()> {
+        session.child.kill().ok();
+        session.child.wait().ok();
+        let old = std::mem::replace(session, self.spawn_session(working_dir, viewers, extra_args)?);
+        old.close();
+        self.response_history.lock().unwrap().clear();
+        *self.output_buffer.lock().unwrap() = String::new();
+        *self.last_output_time.lock().unwrap() = Instant::now();
+        Ok(())
+    }
+
+    /// How long to wait before restarting Claude after it exited. Exits in
+    /// quick succession double the wait each time, up to `MAX_RESTART_BACKOFF`.
+    fn restart_backoff(&self, quick_exits: u32) -> Duration {
+        self.settings.restart_backoff
+            .saturating_mul(2u32.saturating_pow(quick_exits))
+            .min(MAX_RESTART_BACKOFF)
+    }
+
+    /// The next step of the escalation ladder (see `Escalation::next_step`).
+    /// Progress is a task done, or Claude on another task. When Claude is
+    /// stuck in a loop, the responses that made up the loop are forgotten.
+    fn next_escalation_step(&self, stuck: bool) -> Option<EscalationStep> {
+        let progress = (self.count_tasks().0, self.current_task_text());
+        let step = self.escalation.lock().unwrap().next_step(&self.settings.escalation, progress, stuck)?;
+        if stuck {
+            self.response_history.lock().unwrap().clear();
+        }
+        Some(step)
+    }
+
+    /// How the run ends once no task is left open.
+    fn done_reason(&self) -> ExitReason {
+        if self.control.failed.lock().unwrap().is_empty() {
+            ExitReason::AllDone
+        } else {
+            ExitReason::PartialProgress
+        }
+    }
+
+    /// Start the agent in a new PTY, copying its output to the terminal,
+    /// attached viewers and the output buffer.
+    fn spawn_session(&self, working_dir: &Path, viewers: &Arc<Viewers>, extra_args: &[&str]) -> Result<Session> {
+        // Create a new pty
+        let pty_system = native_pty_system();
+        
+        // Create a new pty pair with terminal size
+        let pair = pty_system.openpty(PtySize {
+            rows: 40,
+            cols: 120,
+            pixel_width: 0,
+            pixel_height: 0,
+        }).context("Failed to create PTY")?;
+        
+        // Build the command
+        let mut cmd = CommandBuilder::new(&self.settings.agent_command);
+        cmd.args(self.settings.agent_command_args());
+        cmd.args(extra_args);
+        cmd.cwd(working_dir);
+        
+        // Spawn the command in the pty
+        let child = pair.slave.spawn_command(cmd)
+            .context("Failed to spawn Claude process")?;
+        // Release our copy of the slave so the reader sees EOF when Claude exits
+        drop(pair.slave);
+        
+        // Get reader/writer for the master side
+        let mut reader = pair.master.try_clone_reader()
+            .context("Failed to clone reader")?;
+        let writer = pair.master.take_writer()
+            .context("Failed to get writer")?;
+        
+        // Clone Arc references for the monitoring thread
+        let output_buffer_clone = Arc::clone(&self.output_buffer);
+        let last_output_time_clone = Arc::clone(&self.last_output_time);
+        let buffer_chars = self.settings.output_buffer_chars;
+        let viewers_clone = Arc::clone(viewers);
+        
+        let output_thread = thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) => break, // EOF
+                    Ok(n) => {
+                        let output = String::from_utf8_lossy(&buf[..n]);
+                        
+                        // Print the output exactly as received; the terminal may
+                        // be gone (e.g. a dropped SSH session), so ignore errors
+                        let mut stdout = std::io::stdout();
+                        stdout.write_all(&buf[..n]).ok();
+                        stdout.flush().ok();
+                        viewers_clone.broadcast(&buf[..n]);
+                        
+                        // Update buffer
+                        let mut buffer = output_buffer_clone.lock().unwrap();
+                        buffer.push_str(&output);
+                        
+                        // Keep only recent output
+                        if buffer.len() > buffer_chars {
+                            // Use char_indices to respect UTF-8 boundaries
+                            let skip_chars = buffer.chars().count().saturating_sub(buffer_chars);
+                            *buffer = buffer.chars().skip(skip_chars).collect();
+                        }
+                        
+                        *last_output_time_clone.lock().unwrap() = Instant::now();
+                    }
+                    Err(e) => {
+                        if e.kind() != std::io::ErrorKind::WouldBlock {
+                            eprintln!("Read error: {}", e);
+                            break;
+                        }
+                        thread::sleep(Duration::from_millis(50));
+                    }
+                }
+            }
+        });
+        Ok(Session { child, writer, _master: pair.master, output_thread, started: Instant::now() })
+    }
+
+    /// Type the initial prompt into a new session.
+    fn send_initial_prompt(&self, session: &mut Session, initial_prompt: &str) -> Result<()> {
+        self.update_status("Sending initial prompt to Claude...");
+        if debug_enabled() {
+            eprintln!("[DEBUG] Sending initial prompt: {:?}", initial_prompt);
+        }
+        *self.tool_activity.lock().unwrap() = false;
+        // Write the text first
+        write!(session.writer, "{}", initial_prompt)?;
+        session.writer.flush()?;
+        thread::sleep(Duration::from_millis(50));
+        // Then send Enter key (carriage return)
+        session.writer.write_all(&[0x0D])?; // CR (Enter key)
+        session.writer.flush()?;
+        thread::sleep(Duration::from_millis(100)); // Give PTY time to process
+        self.update_status("Claude is working...");
+        Ok(())
+    }
+
+    /// Text of the first unchecked task.
+    fn current_task_text(&self) -> Option<String> {
+        self.current_task().map(|t| t.text)
+    }
+
+    /// The first task that isn't done, which Claude is expected to be working on.
+    fn current_task(&self) -> Option<Task> {
+        let content = fs::read_to_string(&self.md_file).unwrap_or_default();
+        tasks::parse_tasks(&content).into_iter().find(|t| !self.is_task_done(t))
+    }
+
+    /// Whether a task is checked off, was skipped through the control socket
+    /// or was given up on by the escalation ladder.
+    fn is_task_done(&self, task: &Task) -> bool {
+        self.control.is_done(task)
+    }
+
+    /// Whether the task named by a stop-after-current request is done.
+    fn stop_requested(&self) -> bool {
+        let Some(text) = self.control.stop_after.lock().unwrap().clone() else {
+            return false;
+        };
+        self.known_tasks.lock().unwrap().iter().any(|t| t.text == text && self.is_task_done(t))
+    }
+
+    /// Wait while the scheduler doesn't allow work: until `usage_limit` (or
+    /// one recorded by another run) resets and a run window is open.
+    /// Returns the reason to end the run if interrupted or the deadline
+    /// passes while waiting.
+    fn wait_for_schedule(&self, usage_limit: Option<DateTime<Local>>) -> Result<Option<ExitReason>> {
+        while let Some((until, reason)) = schedule::blocked_until(&self.settings.run_windows, usage_limit, Local::now()) {
+            let time_str = until.format("%a %-I:%M%p").to_string();
+            match reason {
+                WaitReason::UsageLimit => {
+                    self.update_status(&format!("Usage limit in effect, waiting until {}...", time_str));
+                }
+                WaitReason::OutsideWindow => {
+                    self.update_status(&format!("Outside run windows, waiting until {}...", time_str));
+                    self.record.event(EventKind::WindowClosed { until });
+                }
+            }
+            self.record.update(|state| {
+                state.phase = Phase::Waiting;
+                state.waiting_until = Some(until);
+                state.wait_reason = Some(reason);
+            });
+            // Don't wait past the deadline
+            schedule::wait_until(self.deadline.map_or(until, |deadline| until.min(deadline)), &self.interrupted)?;
+            self.record.update(|state| {
+                state.phase = Phase::Running;
+                state.waiting_until = None;
+                state.wait_reason = None;
+            });
+            if *self.interrupted.lock().unwrap() {
+                return Ok(Some(ExitReason::Interrupted));
+            }
+            if self.deadline.is_some_and(|deadline| Local::now() >= deadline) {
+                self.update_status("Deadline reached while waiting. Exiting...");
+                return Ok(Some(ExitReason::DeadlineReached));
+            }
+            if reason == WaitReason::OutsideWindow {
+                self.record.event(EventKind::WindowOpened);
+            }
+        }
+        Ok(None)
+    }
+
+    fn update_status(&self, status: &str) {
+        *self.status.lock().unwrap() = status.to_string();
+        let continues = *self.continue_count.lock().unwrap();
+        self.record.update(|state| {
+            state.status = status.to_string();
+            state.continues = continues;
+        });
+        self.record.event(EventKind::Status { message: status.to_string() });
+        self.display_status();
+    }
+
+    /// Re-read the task file and record tasks that were checked or added
+    /// since the last look. List items added without a checkbox get one.
+    fn track_tasks(&self) {
+        *self.last_task_check.lock().unwrap() = Instant::now();
+        let Ok(mut content) = fs::read_to_string(&self.md_file) else {
+            return;
+        };
+        let previous = self.last_content.lock().unwrap().clone();
+        if content != previous {
+            if let Some(normalized) = tasks::normalize_new_items(&previous, &content) {
+                // Don't overwrite an edit made since we read the file
+                let unchanged = fs::read_to_string(&self.md_file).is_ok_and(|now| now == content);
+                if unchanged && fs::write(&self.md_file, &normalized).is_ok() {
+                    content = normalized;
+                }
+            }
+            // Only Claude's edits are guarded; the operator may change tasks
+            self.note_tool_calls();
+            let claude_edited = std::mem::take(&mut *self.claude_edited_tasks.lock().unwrap());
+            if self.settings.guard_tasks && !previous.is_empty() && claude_edited {
+                self.guard_tasks(&previous, &mut content);
+            }
+            if self.settings.suspicious_policy != SuspiciousPolicy::Off {
+                if previous.is_empty() {
+                    *self.tree_mark.lock().unwrap() =
+                        progress::tree_fingerprint(&self.settings.working_dir(&self.md_file), &self.md_file);
+                } else {
+                    self.check_completions(&mut content);
+                }
+            }
+            *self.last_content.lock().unwrap() = content.clone();
+            *self.task_settings.lock().unwrap() = self.settings_for(&content);
+        }
+
+        let current = tasks::parse_tasks(&content);
+        let mut known = self.known_tasks.lock().unwrap();
+        let mut completed_tasks = Vec::new();
+        for task in current.iter().filter(|t| t.checked) {
+            let was_open = known.iter().any(|k| !k.checked && k.text == task.text);
+            if was_open {
+                self.record.event(EventKind::TaskCompleted { text: task.text.clone() });
+                completed_tasks.push(task.clone());
+            }
+        }
+        let next_task = current.iter().find(|t| !self.is_task_done(t)).cloned();
+        let added: Vec<String> = current
+            .iter()
+            .filter(|t| !t.checked && !known.iter().any(|k| k.text == t.text))
+            .map(|t| t.text.clone())
+            .collect();
+        // On the first look every task is new; only announce later additions
+        if !previous.is_empty() && !added.is_empty() {
+            self.record.event(EventKind::TasksAdded { tasks: added.clone() });
+            self.new_tasks.lock().unwrap().extend(added.iter().cloned());
+            self.update_status(&format!("{} new task(s) added to the task file", added.len()));
+        }
+        *known = current;
+        drop(known);
+
+        for task in completed_tasks {
+            self.run_hook(Hook::TaskComplete, Some(task), None);
+        }
+        let next_text = next_task.as_ref().map(|t| t.text.clone());
+        if next_text.is_some() && *self.started_task.lock().unwrap() != next_text {
+            *self.started_task.lock().unwrap() = next_text;
+            self.run_hook(Hook::TaskStart, next_task, None);
+        }
+
+        // Follow the included and linked files; keep the last good plan if
+        // one of them is being edited into an invalid state
+        if let Ok(plan) = Plan::load(&self.md_file) {
+            for (path, line) in plan.completed_links() {
+                if plan::check_task(&path, line).is_ok() {
+                    self.update_status(&format!("Sub-plan of {} line {} is complete; task checked",
+                        path.display(), line + 1));
+                }
+            }
+            *self.plan.lock().unwrap() = Some(plan);
+        }
+        let (completed, total) = self.count_tasks();
+        self.record.update(|state| {
+            state.tasks_completed = completed;
+            state.tasks_total = total;
+        });
+    }
+
+    /// Undo changes to the existing tasks other than their checkboxes,
+    /// keeping the checkbox states of the edit.
+    fn guard_tasks(&self, previous: &str, content: &mut String) {
+        let Some(tampering) = tasks::guard(previous, content) else {
+            return;
+        };
+        let unchanged = fs::read_to_string(&self.md_file).is_ok_and(|now| now == *content);
+        if !unchanged || fs::write(&self.md_file, &tampering.restored).is_err() {
+            return;
+        }
+        *content = tampering.restored;
+        eprintln!("\n[WARN] The task list was edited beyond its checkboxes and has been restored: {}",
+            tampering.changes.join("; "));
+        self.record.event(EventKind::TasksRestored { changes: tampering.changes.clone() });
+        self.guard_notes.lock().unwrap().extend(tampering.changes);
+    }
+
+    /// Look at the tasks checked off in `content` since the last look and deal
+    /// with the ones that don't look done according to `suspicious_policy`.
+    fn check_completions(&self, content: &mut String) {
+        let checked: Vec<Task> = {
+            let known = self.known_tasks.lock().unwrap();
+            tasks::parse_tasks(content)
+                .into_iter()
+                .filter(|t| t.checked && t.link().is_none())
+                .filter(|t| known.iter().any(|k| !k.checked && k.text == t.text))
+                .collect()
+        };
+        let fingerprint = progress::tree_fingerprint(&self.settings.working_dir(&self.md_file), &self.md_file);
+        let mark = std::mem::replace(&mut *self.tree_mark.lock().unwrap(), fingerprint);
+        if checked.is_empty() {
+            // Only completions move the mark; keep the fingerprint from before
+            *self.tree_mark.lock().unwrap() = mark;
+            return;
+        }
+
+        let mut reasons = Vec::new();
+        if checked.len() >= self.settings.suspicious_batch {
+            reasons.push(format!("{} tasks were checked off at once", checked.len()));
+        }
+        if fingerprint.is_some() && fingerprint == mark {
+            reasons.push("nothing changed in the working tree".to_string());
+        }
+        if !*self.tool_activity.lock().unwrap() {
+            reasons.push("there was no tool activity on screen since the last prompt".to_string());
+        }
+        if reasons.is_empty() {
+            return;
+        }
+
+        let mut reopened = content.clone();
+        let mut to_verify = Vec::new();
+        for task in checked {
+            eprintln!("\n[WARN] \"{}\" was checked off, but {}", task.text, reasons.join(" and "));
+            self.record.event(EventKind::SuspiciousCompletion { text: task.text.clone(), reasons: reasons.clone() });
+            if self.challenged.lock().unwrap().contains(&task.text) {
+                continue;
+            }
+            let suspicion = reasons.join(" and ");
+            match self.settings.suspicious_policy {
+                SuspiciousPolicy::Off | SuspiciousPolicy::Warn => {}
+                SuspiciousPolicy::Reopen => self.challenge(&mut reopened, &task, suspicion),
+                SuspiciousPolicy::Verify => to_verify.push((task, suspicion)),
+            }
+        }
+        if !to_verify.is_empty() {
+            self.verify_queue.lock().unwrap().extend(to_verify);
+            self.start_verification();
+        }
+        if reopened == *content {
+            return;
+        }
+        // Don't overwrite an edit made since we read the file
+        let unchanged = fs::read_to_string(&self.md_file).is_ok_and(|now| now == *content);
+        if unchanged && fs::write(&self.md_file, &reopened).is_ok() {
+            *content = reopened;
+        }
+    }
+
+    /// Reopen a suspicious completion in `content` and queue a challenge for Claude.
+    fn challenge(&self, content: &mut String, task: &Task, suspicion: String) {
+        self.challenged.lock().unwrap().push(task.text.clone());
+        *content = tasks::reopen(content, task.line);
+        let mut vars = self.vars();
+        vars.insert("suspect_task", task.text.clone());
+        vars.insert("suspicion", suspicion);
+        // Checked when the settings were validated
+        let challenge = Template::parse("challenge_prompt", &self.settings.challenge_prompt)
+            .map_or_else(|_| self.settings.challenge_prompt.clone(), |t| t.render(&vars));
+        self.challenges.lock().unwrap().push(challenge);
+        self.update_status(&format!("Reopened \"{}\"", task.text));
+    }
+
+    /// Run the verify command in the background, once for all queued
+    /// suspicious completions, unless it is already running.
+    fn start_verification(&self) {
+        let mut verification = self.verification.lock().unwrap();
+        if verification.is_some() {
+            return;
+        }
+        let tasks = std::mem::take(&mut *self.verify_queue.lock().unwrap());
+        if tasks.is_empty() {
+            return;
+        }
+        let command = self.settings.verify_command.clone();
+        self.update_status(&format!("Verifying {} checked-off task(s) with: {}", tasks.len(), command));
+        let dir = self.settings.working_dir(&self.md_file);
+        let (record, last_failure) = (Arc::clone(&self.record), Arc::clone(&self.last_failure));
+        let run = thread::spawn(move || run_verify(&command, &dir, &record, &last_failure));
+        *verification = Some(Verification { tasks, run });
+    }
+
+    /// Reopen the tasks of a finished verification if the command failed, and
+    /// start the next one. With `wait`, wait for verifications in progress.
+    /// Returns whether a task was reopened.
+    fn finish_verification(&self, wait: bool) -> bool {
+        let mut reopened_any = false;
+        loop {
+            let mut verification = self.verification.lock().unwrap();
+            if !verification.as_ref().is_some_and(|v| wait || v.run.is_finished()) {
+                return reopened_any;
+            }
+            let Verification { tasks, run } = verification.take().expect("checked above");
+            drop(verification);
+            if run.join().ok().flatten().is_some() {
+                reopened_any |= self.reopen_failed(tasks);
+            }
+            self.start_verification();
+        }
+    }
+
+    /// Reopen the tasks whose verification failed, where they still are checked.
+    fn reopen_failed(&self, failed: Vec<(Task, String)>) -> bool {
+        let Ok(content) = fs::read_to_string(&self.md_file) else {
+            return false;
+        };
+        let current = tasks::parse_tasks(&content);
+        let mut reopened = content.clone();
+        for (task, suspicion) in failed {
+            // Claude may have moved or unchecked the task meanwhile
+            if let Some(task) = current.iter().find(|t| t.checked && t.text == task.text) {
+                let suspicion = format!("{} and `{}` failed", suspicion, self.settings.verify_command);
+                self.challenge(&mut reopened, task, suspicion);
+            }
+        }
+        // Don't overwrite an edit made since we read the file
+        let unchanged = fs::read_to_string(&self.md_file).is_ok_and(|now| now == content);
+        reopened != content && unchanged && fs::write(&self.md_file, &reopened).is_ok()
+    }
+
+    /// Tool calls on the screen, e.g. `Bash(cargo test)` or `Update(src/main.rs)`:
+    /// whether there are any other than on the task file, and how many there
+    /// are on the task file.
+    fn scan_tool_calls(&self, buffer: &str) -> (bool, usize) {
+        static ESCAPE: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"\x1b\[[0-9;?]*[ -/]*[@-~]|\x1b\][^\x07]*\x07").unwrap());
+        static TOOL: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r"\b(?:Bash|Edit|MultiEdit|Update|Write|Read|Grep|Glob|Search|Task|WebFetch|NotebookEdit)\(([^)\n]*)")
+                .unwrap()
+        });
+        let text = ESCAPE.replace_all(buffer, "");
+        let file_name = self.md_file.file_name().unwrap_or_default().to_string_lossy();
+        let (on_task_file, other): (Vec<_>, Vec<_>) =
+            TOOL.captures_iter(&text).partition(|c| c[1].contains(file_name.as_ref()));
+        (!other.is_empty(), on_task_file.len())
+    }
+
+    /// Look at the screen for Claude's tool calls: any at all count as
+    /// activity, and new ones on the task file mean the next edit is Claude's.
+    fn note_tool_calls(&self) {
+        let buffer = self.output_buffer.lock().unwrap().clone();
+        let (active, task_file_calls) = self.scan_tool_calls(&buffer);
+        if active {
+            *self.tool_activity.lock().unwrap() = true;
+        }
+        // The buffer is trimmed and cleared, so only an increase means a new call
+        let seen = std::mem::replace(&mut *self.task_file_calls.lock().unwrap(), task_file_calls);
+        if task_file_calls > seen {
+            *self.claude_edited_tasks.lock().unwrap() = true;
+        }
+    }
+
+    fn display_status(&self) {
+        let status = self.status.lock().unwrap();
+        let continues = self.continue_count.lock().unwrap();
+        println!("\n╔════════════════════ CLAUDIA STATUS ════════════════════╗");
+        println!("║ {:<54} ║", status);
+        if *continues > 0 {
+            println!("║ Continues sent: {:<38} ║", continues);
+        }
+        println!("╚════════════════════════════════════════════════════════╝\n");
+    }
+
+    /// Settings in effect for the task Claude is currently working on.
+    fn current_settings(&self) -> Settings {
+        self.task_settings.lock().unwrap().clone()
+    }
+
+    /// Settings with the overrides of the current task in `content`.
+    fn settings_for(&self, content: &str) -> Settings {
+        let tasks = tasks::parse_tasks(content);
+        tasks::current_task(&tasks)
+            .and_then(|t| t.overrides().ok())
+            .and_then(|overrides| self.settings.with_override(&overrides).ok())
+            .unwrap_or_else(|| self.settings.clone())
+    }
+
+    fn summary(&self, reason: ExitReason) -> RunSummary {
+        let (completed, total) = self.count_tasks();
+        let initial = *self.initial_completed.lock().unwrap();
+        RunSummary {
+            run_id: Some(self.record.run_id()),
+            reason,
+            exit_code: reason.code(),
+            md_file: self.md_file.clone(),
+            tasks_total: total,
+            tasks_completed: completed,
+            tasks_completed_this_run: completed.saturating_sub(initial),
+            continues: *self.continue_count.lock().unwrap(),
+            duration_secs: self.started_at.elapsed().as_secs(),
+            error: None,
+        }
+    }
+
+    pub fn run(&self) -> Result<RunSummary> {
+        let result = self.run_session();
+        let reason = match &result {
+            Ok(summary) => summary.reason,
+            Err(e) => RunSummary::from_error(&self.md_file, e).reason,
+        };
+        self.run_hook(Hook::Exit, None, Some(reason));
+        result
+    }
+
+    fn run_session(&self) -> Result<RunSummary> {
+        // Check if the agent command exists
+        let agent = &self.settings.agent_command;
+        if std::process::Command::new("which")
+            .arg(agent)
+            .output()
+            .map(|output| !output.status.success())
+            .unwrap_or(true) {
+            let message = if agent == "claude" {
+                "Claude command not found. Please ensure Claude CLI is installed and in PATH.".to_string()
+            } else {
+                format!("Agent command '{}' not found.", agent)
+            };
+            return Err(ConfigError(message).into());
+        }
+        
+        // May prepare the task file, so run it before reading it
+        self.run_hook(Hook::PreRun, None, None);
+        
+        // Ensure all tasks have checkboxes
+        self.ensure_checkboxes()?;
+        let content = fs::read_to_string(&self.md_file)
+            .context("Failed to read markdown file")?;
+        tasks::validate_overrides(&tasks::parse_tasks(&content), &self.settings)?;
+        let plan = Plan::load(&self.md_file)?;
+        for file in plan.sub_files() {
+            Self::add_checkboxes(&file.path)?;
+        }
+        *self.plan.lock().unwrap() = Some(plan);
+        *self.initial_completed.lock().unwrap() = self.count_tasks().0;
+        self.track_tasks();
+        let (completed, total) = self.count_tasks();
+        self.record.event(EventKind::RunStarted {
+            md_file: self.md_file.clone(),
+            tasks_total: total,
+            tasks_completed: completed,
+        });
+        
+        // Accept control requests from `claudia ctl` while the run is active
+        let _control_server = match ControlServer::start(
+            Arc::clone(&self.record), Arc::clone(&self.control), self.md_file.clone()
+        ) {
+            Ok(server) => Some(server),
+            Err(e) => {
+                eprintln!("[WARN] Control socket unavailable: {:#}", e);
+                None
+            }
+        };
+        
+        // Don't start while a usage limit hit by an earlier run is still in
+        // effect, or outside the run windows
+        if let Some(reason) = self.wait_for_schedule(None)? {
+            return Ok(self.finish(reason));
+        }
+        
+        let initial_prompt = self.initial_prompt()?;
+        *self.prompted_task.lock().unwrap() = self.current_task_text();
+        
+        let working_dir = self.settings.working_dir(&self.md_file);
+        if !working_dir.is_dir() {
+            return Err(ConfigError(format!("Working directory '{}' not found", working_dir.display())).into());
+        }
+        
+        println!("Run ID: {}", self.record.run_id());
+        println!("Starting Claude with task file: {}", self.md_file.display());
+        println!("Working directory: {}", working_dir.display());
+        if !self.settings.model.is_empty() {
+            println!("Model: {}", self.settings.model);
+        }
+        if !self.settings.verify_command.is_empty() {
+            println!("Verify command: {}", self.settings.verify_command);
+        }
+        println!("Idle timeout: {}, max continues: {}",
+            settings::format_duration(self.settings.idle_timeout), self.settings.max_continues);
+        if let Some(deadline) = self.deadline {
+            println!("Deadline: {} (wrap-up requested {} before)",
+                deadline.format("%a %H:%M"), settings::format_duration(self.settings.wrap_up_time));
+        }
+        if !self.settings.run_windows.is_empty() {
+            let windows: Vec<String> = self.settings.run_windows.iter().map(|w| w.to_string()).collect();
+            println!("Run windows: {}", windows.join(", "));
+        }
+        println!();
+        
+        // Create channel for user input (now sends raw bytes)
+        let (user_tx, user_rx) = mpsc::channel::<Vec<u8>>();
+        
+        // Let `claudia attach` mirror the screen and type into it
+        let viewers = Arc::new(Viewers::default());
+        let _attach_server = match AttachServer::start(self.record.dir(), Arc::clone(&viewers), user_tx.clone()) {
+            Ok(server) => Some(server),
+            Err(e) => {
+                eprintln!("[WARN] Attach socket unavailable: {:#}", e);
+                None
+            }
+        };
+        
+        println!("\n════════════════════════════════════════════════════════════");
+        println!("                      CLAUDE SESSION START                   ");
+        println!("════════════════════════════════════════════════════════════\n");
+        
+        let mut session = self.spawn_session(&working_dir, &viewers, &[])?;
+        let mut restarts = 0;
+        // Exits shortly after the session started, in a row
+        let mut quick_exits: u32 = 0;
+        self.send_initial_prompt(&mut session, &initial_prompt)?;
+        
+        // Setup Ctrl+C handler before enabling raw mode
+        let should_exit = Arc::new(Mutex::new(false));
+        let should_exit_clone = Arc::clone(&should_exit);
+        let interrupted_clone = Arc::clone(&self.interrupted);
+        
+        // Only enable raw mode and start input thread if we're in a TTY
+        let is_tty = std::io::stdin().is_terminal();
+        
+        if is_tty {
+            // Enable raw mode for terminal
+            enable_raw_mode().context("Failed to enable raw mode")?;
+        }
+        
+        // Start user input thread only if in TTY
+        let _input_thread = if is_tty {
+            Some(thread::spawn(move || {
+                loop {
+                    // Check if we should exit
+                    if *should_exit_clone.lock().unwrap() {
+                        break;
+                    }
+                    
+                    // Check for keyboard events with a short timeout
+                    if event::poll(Duration::from_millis(50)).unwrap_or(false) {
+                        if let Ok(Event::Key(key_event)) = event::read() {
+                            // Check for Ctrl+C
+                            if matches!(key_event.code, KeyCode::Char('c')) && 
+                               key_event.modifiers.contains(KeyModifiers::CONTROL) {
+                                // Let the main loop shut down and report the run
+                                *interrupted_clone.lock().unwrap() = true;
+                                break;
+                            }
+                            
+                            let bytes = key_bytes(key_event.code);
+                            if !bytes.is_empty() && user_tx.send(bytes).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }))
+        } else {
+            None
+        };
+        
+        // Main monitoring loop
+        let reason = loop {
+            thread::sleep(Duration::from_millis(100)); // Faster response for user input
+            
+            // Check for Ctrl+C (from the input thread or the signal handler)
+            if *self.interrupted.lock().unwrap() {
+                self.update_status("Interrupted by user. Exiting...");
+                session.child.kill().ok();
+                break ExitReason::Interrupted;
+            }
+            
+            // Check for requests from the control socket
+            if *self.control.abort.lock().unwrap() {
+                self.update_status("Aborted through the control socket. Exiting...");
+                session.child.kill().ok();
+                break ExitReason::Interrupted;
+            }
+            // Ask Claude to wrap up shortly before the deadline, stop at the deadline
+            if let Some(deadline) = self.deadline {
+                let now = Local::now();
+                if now >= deadline {
+                    self.update_status("Deadline reached. Exiting...");
+                    session.child.kill().ok();
+                    break ExitReason::DeadlineReached;
+                }
+                let wrap_up_at = deadline - chrono::Duration::from_std(self.settings.wrap_up_time).unwrap_or_default();
+                if now >= wrap_up_at && !*self.wrap_up_sent.lock().unwrap() {
+                    *self.wrap_up_sent.lock().unwrap() = true;
+                    self.update_status(&format!("Deadline at {}. Asking Claude to wrap up...", deadline.format("%H:%M")));
+                    self.send_message(&mut session.writer, &self.settings.wrap_up_message)?;
+                    self.record.event(EventKind::WrapUpRequested { deadline });
+                    *self.output_buffer.lock().unwrap() = String::new();
+                    *self.last_output_time.lock().unwrap() = Instant::now();
+                    self.update_status("Claude is wrapping up...");
+                }
+            }
+            
+            if let Some(message) = self.control.inject.lock().unwrap().pop_front() {
+                self.update_status("Sending message from the control socket...");
+                self.send_message(&mut session.writer, &message)?;
+                self.record.event(EventKind::MessageInjected { message });
+                *self.output_buffer.lock().unwrap() = String::new();
+                *self.last_output_time.lock().unwrap() = Instant::now();
+                self.update_status("Claude is working...");
+            }
+            
+            // Check for user input
+            if let Ok(user_bytes) = user_rx.try_recv() {
+                // User pressed a key, send raw bytes to Claude
+                session.writer.write_all(&user_bytes)?;
+                session.writer.flush()?;
+                
+                // Only reset tracking for actual character input (not just arrow keys)
+                if !user_bytes.is_empty() && user_bytes[0] != 0x1B {
+                    *self.last_output_time.lock().unwrap() = Instant::now();
+                }
+            }
+            
+            // Check if process is still running
+            match session.child.try_wait() {
+                Ok(Some(status)) => {
+                    self.update_status(&format!("Claude process exited with status: {:?}", status));
+                    self.record.event(EventKind::AgentExited { status: format!("{:?}", status) });
+                    // A suspicious completion still being verified may be reopened
+                    let complete = self.check_all_tasks_completed() && !self.finish_verification(true);
+                    let failure = if complete { self.verify() } else { None };
+                    if complete && failure.is_none() {
+                        break self.done_reason();
+                    }
+                    if restarts >= self.settings.max_restarts {
+                        if !status.success() {
+                            break ExitReason::AgentCrashed;
+                        }
+                        break ExitReason::PartialProgress;
+                    }
+                    
+                    // Tasks are still open: start Claude again, backing off if it keeps exiting
+                    restarts += 1;
+                    quick_exits = if session.started.elapsed() < QUICK_EXIT { quick_exits + 1 } else { 0 };
+                    let delay = self.restart_backoff(quick_exits.saturating_sub(1));
+                    eprintln!("\n\n  Claude exited with tasks open. Restarting in {} ({} of {})...\n",
+                        settings::format_duration(delay), restarts, self.settings.max_restarts);
+                    self.record.event(EventKind::AgentRestarted {
+                        attempt: restarts,
+                        delay_secs: delay.as_secs(),
+                    });
+                    schedule::wait_until(Local::now() + chrono::Duration::from_std(delay).unwrap_or_default(), &self.interrupted)?;
+                    if *self.interrupted.lock().unwrap() {
+                        break ExitReason::Interrupted;
+                    }
+                    let resume: &[&str] = if self.settings.restart_resume { &["--continue"] } else { &[] };
+                    self.respawn(&mut session, &working_dir, &viewers, resume)?;
+                    let prompt = failure.unwrap_or_else(|| self.render("scoped_prompt", &self.settings.scoped_prompt));
+                    self.send_initial_prompt(&mut session, &prompt)?;
+                    *self.prompted_task.lock().unwrap() = self.current_task_text();
+                    continue;
+                }
+                Ok(None) => {
+                    // Process is still running
+                }
+                Err(e) => {
+                    self.update_status(&format!("Error checking process status: {}", e));
+                    break ExitReason::AgentCrashed;
+                }
+            }
+            
+            // Reopen suspicious completions the background verify command failed
+            self.finish_verification(false);
+
+            // Pick up checkbox changes every couple of seconds
+            if self.last_task_check.lock().unwrap().elapsed() > Duration::from_secs(2) {
+                self.track_tasks();
+                if self.stop_requested() {
+                    self.update_status("Requested task finished. Stopping...");
+                    session.child.kill()?;
+                    break ExitReason::Stopped;
+                }
+            }
+            
+            let buffer = self.output_buffer.lock().unwrap().clone();
+            let time_since_output = self.last_output_time.lock().unwrap().elapsed();
+            self.note_tool_calls();
+            // Sampled all along, so there is a baseline by the time Claude goes quiet
+            let busy_processes = self.busy_processes(&session);
+            
+            // Check for usage limit (time shown at bottom right)
+            if let Some(wait_until) = Self::check_usage_limit(&buffer) {
+                let time_str = wait_until.format("%-I:%M%p").to_string().to_lowercase();
+                
+                if debug_enabled() {
+                    eprintln!("[DEBUG] Usage limit detected. Wait until: {}", time_str);
+                }
+                
+                // Clear any pending output first
+                thread::sleep(Duration::from_millis(100));
+                
+                // Use eprintln to write to stderr which won't be overwritten by Claude's stdout
+                eprintln!("\n\n");
+                eprintln!("════════════════════════════════════════════════════════════");
+                eprintln!("                    USAGE LIMIT DETECTED                     ");
+                eprintln!("════════════════════════════════════════════════════════════");
+                eprintln!();
+                eprintln!("  Claude has reached its usage limit.");
+                eprintln!("  Waiting until {} to continue...", time_str);
+                eprintln!();
+                eprintln!("  This message will remain visible during the wait.");
+                eprintln!();
+                eprintln!("════════════════════════════════════════════════════════════");
+                eprintln!("\n");
+                
+                // Also print to stdout with some newlines to push Claude's output down
+                println!("\n\n\n\n\n");
+                
+                self.record.event(EventKind::UsageLimit { until: wait_until });
+                self.notify(Notice::UsageLimit, self.current_task_text(),
+                    &format!("Claude hit its usage limit; waiting until {}", time_str), None);
+                self.run_hook(Hook::Limit, self.current_task(), None);
+                state::record_usage_limit(wait_until);
+                // Also waits for the next run window if the limit resets outside one
+                if let Some(reason) = self.wait_for_schedule(Some(wait_until))? {
+                    session.child.kill().ok();
+                    break reason;
+                }
+                self.record.event(EventKind::Resumed);
+                
+                // Paused through the control socket: the Continue waits for `ctl resume`
+                if *self.control.paused.lock().unwrap() {
+                    self.update_status("Usage limit reset, but the run is paused; no Continue sent");
+                    *self.output_buffer.lock().unwrap() = String::new();
+                    *self.last_output_time.lock().unwrap() = Instant::now();
+                    continue;
+                }
+                
+                *self.continue_count.lock().unwrap() += 1;
+                
+                // Clear and show resuming message (use stderr)
+                eprintln!("\n════════════════════════════════════════════════════════════");
+                eprintln!("                      RESUMING SESSION                       ");
+                eprintln!("════════════════════════════════════════════════════════════\n");
+                
+                self.update_status("Sending Continue after usage limit wait...");
+                self.record.event(EventKind::ContinueSent { count: *self.continue_count.lock().unwrap() });
+                self.send_message(&mut session.writer, &self.render("continue_message", &self.settings.continue_message))?;
+                *self.output_buffer.lock().unwrap() = String::new();
+                *self.last_output_time.lock().unwrap() = Instant::now();
+                self.update_status("Claude is working...");
+                continue;
+            }
+            
+            // Check for bypass permissions prompt
+            if Self::check_bypass_permissions_prompt(&buffer) {
+                self.update_status("Detected bypass permissions prompt, accepting...");
+                if debug_enabled() {
+                    eprintln!("[DEBUG] Bypass permissions prompt detected, sending '2' to accept");
+                }
+                // Send "2" to accept
+                session.writer.write_all(b"2")?;
+                session.writer.flush()?;
+                thread::sleep(Duration::from_millis(50));
+                // Send Enter key
+                session.writer.write_all(&[0x0D])?;
+                session.writer.flush()?;
+                *self.output_buffer.lock().unwrap() = String::new();
+                *self.last_output_time.lock().unwrap() = Instant::now();
+                continue;
+            }
+            
+            // Check if we need to send Continue
+            // Logic: If "esc to interrupt" is NOT present (Claude has stopped) AND 
+            //        we haven't had output for the idle timeout AND tasks aren't all completed
+            if !Self::is_claude_running(&buffer) && time_since_output > self.current_settings().idle_timeout
+                && !self.wait_for_processes(&busy_processes) {
+                // Check if all tasks are completed. Look at the file again first
+                // and give an edit in progress (e.g. new tasks being appended)
+                // time to land, so a run doesn't end just before they appear.
+                self.track_tasks();
+                if self.check_all_tasks_completed() {
+                    if !self.task_file_settled() {
+                        continue;
+                    }
+                    // A suspicious completion still being verified may be reopened
+                    if self.finish_verification(true) {
+                        continue;
+                    }
+                    if !self.settings.verify_command.is_empty() {
+                        self.update_status(&format!("All tasks checked. Verifying with: {}", self.settings.verify_command));
+                    }
+                    if let Some(failure) = self.verify() {
+                        // Send Claude back to fix it; this counts as a Continue
+                        let count = *self.continue_count.lock().unwrap();
+                        if count >= self.settings.max_continues {
+                            self.update_status("Verification failed and the Continue limit is reached. Exiting...");
+                            session.child.kill()?;
+                            break ExitReason::ContinueBudgetExhausted;
+                        }
+                        *self.continue_count.lock().unwrap() += 1;
+                        self.record.event(EventKind::ContinueSent { count: count + 1 });
+                        self.send_message(&mut session.writer, &failure)?;
+                        *self.output_buffer.lock().unwrap() = String::new();
+                        *self.last_output_time.lock().unwrap() = Instant::now();
+                        self.update_status("Claude is fixing the verification failure...");
+                        continue;
+                    }
+                    if self.control.failed.lock().unwrap().is_empty() {
+                        self.update_status("All tasks completed! Exiting...");
+                    } else {
+                        self.update_status("No open tasks left, some failed. Exiting...");
+                    }
+                    session.child.kill()?;
+                    break self.done_reason();
+                }
+                
+                // Paused through the control socket: leave Claude alone
+                if *self.control.paused.lock().unwrap() {
+                    continue;
+                }
+                
+                // Claude has finished wrapping up; don't start anything new before the deadline
+                if *self.wrap_up_sent.lock().unwrap() {
+                    self.update_status("Claude wrapped up before the deadline. Exiting...");
+                    session.child.kill()?;
+                    break ExitReason::DeadlineReached;
+                }
+                
+                // Outside the run windows: don't let Claude start on more work
+                if schedule::blocked_until(&self.settings.run_windows, None, Local::now()).is_some() {
+                    eprintln!("\n\n  Outside the run windows; Claude is paused until the next one opens.\n");
+                    if let Some(reason) = self.wait_for_schedule(None)? {
+                        session.child.kill().ok();
+                        break reason;
+                    }
+                }
+                
+                // Claude stopped before finishing: take the next step of the escalation
+                // ladder. A detected loop skips to a step that does more than talk.
+                let repeated = self.check_repeated_pattern(&buffer, &self.current_settings());
+                let stuck = self.check_stall() || repeated;
+                let Some(step) = self.next_escalation_step(stuck) else {
+                    self.update_status("Claude keeps repeating itself or making no progress. Exiting...");
+                    eprintln!("\n[ERROR] Claude appears to be stuck in a loop. Exiting to prevent infinite retries.");
+                    self.notify(Notice::Stuck, self.current_task_text(), "Claude is stuck in a loop; the run is ending", None);
+                    self.run_hook(Hook::Stuck, self.current_task(), None);
+                    session.child.kill()?;
+                    break ExitReason::StuckLoop;
+                };
+                
+                *self.continue_count.lock().unwrap() += 1;
+                let count = *self.continue_count.lock().unwrap();
+                
+                // Also check if we've sent too many continues
+                if count > self.settings.max_continues {
+                    self.update_status("Maximum continue limit reached. Exiting...");
+                    eprintln!("\n[ERROR] Sent {} Continue commands. Something may be wrong. Exiting.",
+                        self.settings.max_continues);
+                    session.child.kill()?;
+                    break ExitReason::ContinueBudgetExhausted;
+                }
+                
+                self.update_status(&format!("Claude stopped. {} (#{})...", step.describe(), count));
+                self.record.event(EventKind::ContinueSent { count });
+                let current = self.current_task_text();
+                if step != EscalationStep::Continue {
+                    self.record.event(EventKind::Escalated { step, task: current.clone().unwrap_or_default() });
+                }
+                let message = match step {
+                    // Claude moved on to another task since it was last prompted
+                    EscalationStep::Continue if !self.settings.task_prompt.is_empty()
+                        && *self.prompted_task.lock().unwrap() != current => {
+                        Some(self.render("task_prompt", &self.settings.task_prompt))
+                    }
+                    EscalationStep::Continue => Some(self.render("continue_message", &self.settings.continue_message)),
+                    EscalationStep::Firm => Some(self.render("firm_message", &self.settings.firm_message)),
+                    EscalationStep::Clear => {
+                        self.send_message(&mut session.writer, "/clear")?;
+                        thread::sleep(Duration::from_secs(1));
+                        self.response_history.lock().unwrap().clear();
+                        Some(self.render("scoped_prompt", &self.settings.scoped_prompt))
+                    }
+                    EscalationStep::Restart => {
+                        self.restart_session(&mut session, &working_dir, &viewers)?;
+                        None
+                    }
+                    EscalationStep::Fail => match current.clone() {
+                        Some(text) => {
+                            eprintln!("\n[WARN] Giving up on task: {}", text);
+                            self.control.failed.lock().unwrap().push(text.clone());
+                            self.record.event(EventKind::TaskFailed { text: text.clone() });
+                            self.notify(Notice::TaskFailed, Some(text), "Claude made no progress on the task; it was marked as failed", None);
+                            Some(self.render("failed_message", &self.settings.failed_message))
+                        }
+                        // Nothing unchecked to give up on (e.g. a sub-plan is still open)
+                        None => Some(self.render("continue_message", &self.settings.continue_message)),
+                    },
+                };
+                *self.prompted_task.lock().unwrap() = self.current_task_text();
+                if let Some(message) = message {
+                    let file_name = self.md_file.file_name().unwrap_or_default().to_string_lossy();
+                    let new_tasks: Vec<String> = self.new_tasks.lock().unwrap().drain(..).collect();
+                    let mut message = if new_tasks.is_empty() {
+                        message
+                    } else {
+                        format!("{} New tasks were added to {}: {}", message, file_name, new_tasks.join("; "))
+                    };
+                    let restored: Vec<String> = self.guard_notes.lock().unwrap().drain(..).collect();
+                    if !restored.is_empty() {
+                        message.push_str(&format!(
+                            " Your edit to {} changed the tasks themselves ({}), so the task text was restored. \
+                            Only change [ ] to [x]; don't reword, reorder or remove tasks.",
+                            file_name, restored.join("; ")));
+                    }
+                    for challenge in self.challenges.lock().unwrap().drain(..) {
+                        message.push(' ');
+                        message.push_str(&challenge);
+                    }
+                    self.send_message(&mut session.writer, &message)?;
+                }
+                *self.output_buffer.lock().unwrap() = String::new();
+                *self.last_output_time.lock().unwrap() = Instant::now();
+                self.update_status("Claude is working...");
+            }
+            // If "esc to interrupt" is present, Claude is still working - just wait
+        };
+        
+        // Signal input thread to exit
+        *should_exit.lock().unwrap() = true;
+        
+
+        // Disable raw mode before printing final messages (only if it was enabled)
+        if is_tty {
+            disable_raw_mode().ok();
+        }
+        
+        // Wait for threads to finish
+        session.close();
+        // Give input thread time to exit cleanly
+        thread::sleep(Duration::from_millis(100));
+        
+        println!("\n════════════════════════════════════════════════════════════");
+        println!("                       CLAUDE SESSION END                    ");
+        println!("════════════════════════════════════════════════════════════");
+        
+        // Display final summary
+        Ok(self.finish(reason))
+    }
+
+
+    /// Record the end of the run and print the final summary.
+    fn finish(&self, reason: ExitReason) -> RunSummary {
+        // Before the tasks are counted, in case it touches the task file
+        self.run_hook(Hook::PostRun, None, Some(reason));
+        self.track_tasks();
+        let summary = self.summary(reason);
+        self.record.update(|state| {
+            state.phase = Phase::Finished;
+            state.status = reason.describe().to_string();
+            state.continues = summary.continues;
+            state.reason = Some(reason);
+            state.exit_code = Some(reason.code());
+        });
+        self.record.event(EventKind::RunFinished {
+            reason,
+            exit_code: reason.code(),
+            tasks_total: summary.tasks_total,
+            tasks_completed: summary.tasks_completed,
+            continues: summary.continues,
+        });
+        self.notify(Notice::RunFinished, self.current_task_text(), reason.describe(), Some(reason.code()));
+        println!("\n╔═══════════════════ CLAUDIA SUMMARY ═══════════════════╗");
+        println!("║ Total Continue commands sent: {:<23} ║", summary.continues);
+        println!("║ Tasks completed: {:<36} ║",
+            format!("{}/{} ({} this run)", summary.tasks_completed, summary.tasks_total, summary.tasks_completed_this_run));
+        println!("║ {:<53} ║", reason.describe());
+        println!("║ Exit code: {:<42} ║", reason.code());
+        println!("╚═══════════════════════════════════════════════════════╝\n");
+        println!("Run {} recorded. See `claudia report {}` for details.", self.record.run_id(), self.record.run_id());
+        // Failed deliveries are logged, so finish them before writing the report
+        self.notifier.flush();
+        self.write_report();
+        
+        summary
+    }
+
+    /// Save the Markdown report next to the run's state, so it is there
+    /// without running `claudia report` (e.g. after an overnight run).
+    fn write_report(&self) {
+        let path = self.record.dir().join("report.md");
+        let result = state::load_events(self.record.dir())
+            .and_then(|events| Ok(fs::write(&path, report::render(&self.record.state(), &events))?));
+        if let Err(e) = result {
+            eprintln!("[WARN] Failed to write {}: {:#}", path.display(), e);
+        }
+    }
+
+    fn check_usage_limit(buffer: &str) -> Option<DateTime<Local>> {
+        // Look for specific usage limit patterns from Claude
+        // Common patterns: "usage limit", "rate limit", "try again at", "please wait until"
+        let recent = Self::safe_suffix(buffer, 2000);
+        let recent_lower = recent.to_lowercase();
+        
+        // Check if this is actually a usage limit message
+        if !recent_lower.contains("usage limit") && 
+           !recent_lower.contains("rate limit") && 
+           !recent_lower.contains("try again") &&
+           !recent_lower.contains("please wait") {
+            return None;
+        }
+        
+        // Now look for time pattern near the usage limit message
+        let time_pattern = Regex::new(r"(\d{1,2})([:.]?\d{0,2})\s*([ap]\.?m)").ok()?;
+        
+        if let Some(captures) = time_pattern.captures(&recent_lower) {
+            let hour = captures.get(1)?.as_str().parse::<u32>().ok()?;
+            let minutes_part = captures.get(2)?.as_str();
+            let am_pm = captures.get(3)?.as_str();
+            
+            let minutes = if minutes_part.len() > 1 {
+                minutes_part.trim_start_matches(':').trim_start_matches('.').parse::<u32>().unwrap_or(0)
+            } else {
+                0
+            };
+            
+            let hour_24 = if am_pm.starts_with('p') && hour != 12 {
+                hour + 12
+            } else if am_pm.starts_with('a') && hour == 12 {
+                0
+            } else {
+                hour
+            };
+            
+            if let Some(time) = NaiveTime::from_hms_opt(hour_24, minutes, 0) {
+                let now = Local::now();
+                let mut wait_until = now.date_naive().and_time(time).and_local_timezone(Local).unwrap();
+                
+                if wait_until <= now {
+                    wait_until += chrono::Duration::days(1);
+                }
+                
+                return Some(wait_until);
+            }
+        }
+        
+        None
+    }
+
+    fn is_claude_running(buffer: &str) -> bool {
+        // Check the last 200 chars for "esc to interrupt"
+        // If "esc to interrupt" is present, Claude is still running
+        let recent = Self::safe_suffix(buffer, 200);
+        recent.to_lowercase().contains("esc to interrupt")
+    }
+    
+    fn check_bypass_permissions_prompt(buffer: &str) -> bool {
+        // Check for the bypass permissions prompt
+        let recent = Self::safe_suffix(buffer, 1500);
+        let recent_lower = recent.to_lowercase();
+        
+        // Look for the characteristic prompt patterns
+        if recent_lower.contains("bypass permissions mode") &&
+           recent_lower.contains("1. no, exit") &&
+           recent_lower.contains("2. yes, i accept") {
+            return true;
+        }
+        
+        // Also check for variations
+        if recent.contains("WARNING: Claude Code running in Bypass Permissions mode") &&
+           (recent.contains("1. No, exit") || recent.contains("2. Yes, I accept")) {
+            return true;
+        }
+        
+        false
+    }
+    
+    /// Run the verify command, if one is configured, in the working directory.
+    /// Returns the message telling Claude what failed, or `None` if it passed.
+    fn verify(&self) -> Option<String> {
+        let command = &self.settings.verify_command;
+        if command.is_empty() {
+            return None;
+        }
+        run_verify(command, &self.settings.working_dir(&self.md_file), &self.record, &self.last_failure)
+    }
+
+    fn check_all_tasks_completed(&self) -> bool {
+        // With sub-plans, every file of the plan has to be complete
+        if let Some(plan) = self.plan.lock().unwrap().as_ref().filter(|p| !p.sub_files().is_empty()) {
+            let (_, total) = plan.counts(&|t| t.checked);
+            return total > 0 && plan.is_complete(&|t| self.is_task_done(t));
+        }
+        // If there are checkboxes and all are checked (or skipped or failed), tasks are complete
+        let (checked, total) = self.count_tasks();
+        let skipped = if self.control.skipped.lock().unwrap().is_empty() && self.control.failed.lock().unwrap().is_empty() {
+            0
+        } else {
+            let content = fs::read_to_string(&self.md_file).unwrap_or_default();
+            tasks::parse_tasks(&content).iter().filter(|t| !t.checked && self.is_task_done(t)).count()
+        };
+        total > 0 && checked + skipped == total
+    }
+    
+    /// Whether the task file has been left alone for a couple of seconds.
+    fn task_file_settled(&self) -> bool {
+        fs::metadata(&self.md_file)
+            .and_then(|m| m.modified())
+            .map(|modified| modified.elapsed().unwrap_or_default() > Duration::from_secs(2))
+            .unwrap_or(true)
+    }
+
+    /// Returns `(checked, total)` checkbox counts for the markdown file, or
+    /// for the whole plan if it includes or links to other files.
+    fn count_tasks(&self) -> (usize, usize) {
+        if let Some(plan) = self.plan.lock().unwrap().as_ref().filter(|p| !p.sub_files().is_empty()) {
+            return plan.counts(&|t| t.checked);
+        }
+        fs::read_to_string(&self.md_file).map_or((0, 0), |content| tasks::counts(&content))
+    }
+    
+    fn ensure_checkboxes(&self) -> Result<()> {
+        Self::add_checkboxes(&self.md_file)
+    }
+
+    fn add_checkboxes(md_file: &Path) -> Result<()> {
+        // Read the markdown file
+        let content = fs::read_to_string(md_file)
+            .context("Failed to read markdown file")?;
+        
+        // Write back if modified
+        if let Some(new_content) = tasks::normalize_checkboxes(&content) {
+            fs::write(md_file, new_content)
+                .context("Failed to write updated markdown file")?;
+            println!("Added checkboxes to tasks in {}", md_file.display());
+        }
+        
+        Ok(())
+    }
+    
+    /// The processes of Claude's tree that are using the CPU or disk; empty
+    /// if none are or watching is turned off.
+    fn busy_processes(&self, session: &Session) -> Vec<String> {
+        if !self.settings.watch_processes {
+            return Vec::new();
+        }
+        self.processes.lock().unwrap().busy(
+            session.child.process_id(), self.settings.busy_cpu_percent, self.settings.max_busy_wait)
+    }
+
+    /// Whether to hold off a Continue because Claude's processes are busy
+    /// although the screen is quiet, e.g. with a long build that prints nothing.
+    fn wait_for_processes(&self, busy: &[String]) -> bool {
+        let was_waiting = std::mem::replace(&mut *self.waiting_on_processes.lock().unwrap(), !busy.is_empty());
+        if !busy.is_empty() && !was_waiting {
+            self.update_status(&format!("No output, but Claude's processes are busy ({})", busy.join(", ")));
+        }
+        !busy.is_empty()
+    }
+
+    /// Whether Claude was sent `stall_continues` Continues in a row without
+    /// changing a file or making progress on the tasks.
+    fn check_stall(&self) -> bool {
+        let (completed, total) = self.count_tasks();
+        let snapshot = Snapshot {
+            tree: progress::tree_fingerprint(&self.settings.working_dir(&self.md_file), &self.md_file),
+            tasks: (completed, total, self.current_task_text()),
+        };
+        let Some(stall) = self.stall.lock().unwrap().observe(snapshot, self.settings.stall_continues) else {
+            return false;
+        };
+        eprintln!("\n[WARN] Claude seems stalled: {}", stall.describe());
+        self.record.event(EventKind::Stalled { continues: stall.continues, files_watched: stall.files_watched });
+        true
+    }
+
+    /// Whether Claude's latest responses repeat themselves, allowing for small
+    /// differences (spinners, counters, timestamps) and for cycles such as
+    /// A, B, A, B.
+    fn check_repeated_pattern(&self, current_buffer: &str, settings: &Settings) -> bool {
+        let mut history = self.response_history.lock().unwrap();
+        history.push(loops::normalize(current_buffer, settings.loop_compare_chars));
+
+        // Keep enough responses for the window and the longest cycle
+        let keep = settings.loop_window.max(2 * loops::MAX_PERIOD);
+        let excess = history.len().saturating_sub(keep);
+        history.drain(..excess);
+
+        match loops::find_cycle(&history, settings.loop_window, settings.loop_similarity) {
+            Some(1) => true,
+            Some(period) => {
+                eprintln!("\n[WARN] Claude's responses repeat in a cycle of {}", period);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Bytes to send to the PTY for a key pressed in claudia's (or an attached) terminal.
+pub fn key_bytes(code: KeyCode) -> Vec<u8> {
+    match code {
+        // Arrow keys
+        KeyCode::Up => vec![0x1B, b'[', b'A'],
+        KeyCode::Down => vec![0x1B, b'[', b'B'],
+        KeyCode::Right => vec![0x1B, b'[', b'C'],
+        KeyCode::Left => vec![0x1B, b'[', b'D'],
+        // Enter key
+        KeyCode::Enter => vec![0x0D],
+        // Regular characters
+        KeyCode::Char(c) => c.to_string().into_bytes(),
+        // Backspace
+        KeyCode::Backspace => vec![0x7F],
+        // Tab
+        KeyCode::Tab => vec![0x09],
+        // Escape
+        KeyCode::Esc => vec![0x1B],
+        // Other keys - ignore for now
+        _ => vec![],
+    }
+}
+
+/// Run the verify `command` in `dir` and record the outcome, keeping the end
+/// of its output in `last_failure`. Returns the message telling Claude what
+/// failed, or `None` if it passed.
+fn run_verify(command: &str, dir: &Path, record: &RunRecord, last_failure: &Mutex<String>) -> Option<String> {
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(dir)
+        .output();
+    let (status, text) = match output {
+        Ok(output) if output.status.success() => {
+            record.event(EventKind::VerifyPassed { command: command.to_string() });
+            last_failure.lock().unwrap().clear();
+            return None;
+        }
+        Ok(output) => {
+            let text = String::from_utf8_lossy(&output.stdout).into_owned()
+                + &String::from_utf8_lossy(&output.stderr);
+            (output.status.to_string(), text)
+        }
+        Err(e) => (format!("could not run: {}", e), String::new()),
+    };
+    record.event(EventKind::VerifyFailed { command: command.to_string(), status: status.clone() });
+
+    // Quote the end of the output, where the errors usually are
+    let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    let tail = lines[lines.len().saturating_sub(20)..].join("\n");
+    *last_failure.lock().unwrap() = tail.clone();
+    let mut message = format!(
+        "All tasks are marked [x], but the verify command `{}` failed ({}).", command, status
+    );
+    if !tail.is_empty() {
+        message.push_str(&format!(" The end of its output:\n{}\n", tail));
+    }
+    message.push_str(" Fix the problem so that the command succeeds.");
+    Some(message)
+}
@@ -0,0 +1,404 @@
+use anyhow::{Context, Result};
+use chrono::Local;
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::thread;
+use std::time::Duration;
+
+use crate::claudia::Claudia;
+use crate::config::{self, CONFIG_FILE_NAME};
+use crate::control::{self, Request};
+use crate::hooks::Hook;
+use crate::plan::Plan;
+use crate::schedule::WaitReason;
+use crate::settings::{self, Settings, SuspiciousPolicy};
+use crate::state::{self, Phase, RunState};
+use crate::tasks::{self, Severity};
+use crate::template::Template;
+use crate::{report, ConfigError, ExitReason, SettingsArgs};
+
+fn read_task_file(md_file: &Path) -> Result<String> {
+    if !md_file.exists() {
+        return Err(ConfigError(format!("File '{}' not found", md_file.display())).into());
+    }
+    fs::read_to_string(md_file).with_context(|| format!("Failed to read {}", md_file.display()))
+}
+
+/// `claudia validate`: lint the task file and the configuration that applies to it.
+pub fn validate(md_file: &Path, settings: &SettingsArgs) -> Result<i32> {
+    let content = read_task_file(md_file)?;
+    let settings = settings.sources(Some(md_file)).resolve()?.settings;
+
+    let issues = tasks::lint(&content, &settings);
+    for issue in &issues {
+        let severity = match issue.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match issue.line {
+            Some(line) => println!("{}:{}: {}: {}", md_file.display(), line, severity, issue.message),
+            None => println!("{}: {}: {}", md_file.display(), severity, issue.message),
+        }
+    }
+
+    // Included and linked files must exist and must not include each other
+    let plan_error = Plan::load(md_file).err();
+    // A prompt template file must be readable and valid
+    let template_error = Claudia::prompt_template(md_file, &settings).err();
+    for e in plan_error.iter().chain(&template_error) {
+        println!("{}: error: {}", md_file.display(), e);
+    }
+
+    let errors = issues.iter().filter(|i| i.severity == Severity::Error).count()
+        + plan_error.iter().count()
+        + template_error.iter().count();
+    let warnings = issues.iter().filter(|i| i.severity == Severity::Warning).count();
+    let task_count = tasks::parse_tasks(&tasks::normalize_checkboxes(&content).unwrap_or(content)).len();
+    println!("{} tasks, {} errors, {} warnings", task_count, errors, warnings);
+
+    Ok(if errors > 0 { ExitReason::ConfigError.code() } else { 0 })
+}
+
+/// `claudia list`: print the tasks as a run would see them.
+pub fn list(md_file: &Path) -> Result<i32> {
+    let content = read_task_file(md_file)?;
+    let normalized = tasks::normalize_checkboxes(&content);
+    let tasks = tasks::parse_tasks(normalized.as_deref().unwrap_or(&content));
+
+    if tasks.is_empty() {
+        println!("No tasks found in {}", md_file.display());
+        return Ok(0);
+    }
+
+    let current = tasks::current_task(&tasks).map(|t| t.id);
+    let plan = Plan::load(md_file).ok();
+    println!("{:>4}  {:<5} {:>5}  Task", "ID", "State", "Line");
+    for task in &tasks {
+        let state = if task.checked { "[x]" } else { "[ ]" };
+        let marker = if Some(task.id) == current { ">" } else { " " };
+        let sub_plan = plan.as_ref()
+            .and_then(|plan| plan.root().link(task).map(|key| (plan, key)))
+            .map(|(plan, key)| {
+                let (done, total) = plan.file_counts(key);
+                format!("  (sub-plan: {}/{} done)", done, total)
+            })
+            .unwrap_or_default();
+        println!("{}{:>3}  {:<5} {:>5}  {}{}", marker, task.id, state, task.line + 1, task.text, sub_plan);
+    }
+    if let Some(plan) = plan.as_ref().filter(|p| !p.sub_files().is_empty()) {
+        println!("\nIncluded and linked files:");
+        for file in plan.sub_files() {
+            let (done, total) = plan.file_counts(&file.path);
+            println!("  {}  {}/{} completed", file.path.display(), done, total);
+        }
+    }
+
+    let completed = tasks.iter().filter(|t| t.checked).count();
+    println!("\n{}/{} completed", completed, tasks.len());
+    if normalized.is_some() {
+        println!("(Some list items have no checkbox yet; they will get one when the run starts)");
+    }
+    Ok(0)
+}
+
+/// `claudia run --dry-run`: print everything the run would do, then stop.
+pub fn dry_run(md_file: &Path, settings: &SettingsArgs) -> Result<i32> {
+    let content = read_task_file(md_file)?;
+    let resolved = settings.sources(Some(md_file)).resolve()?;
+    let settings = &resolved.settings;
+    let section = |title: &str| println!("\n═══ {} ═══\n", title);
+
+    println!("Dry run for {} (nothing will be started or written)", md_file.display());
+
+    section("Effective configuration");
+    config::show(&resolved);
+
+    section("Checkbox changes");
+    // The run adds checkboxes to the task file and to every file it includes or links to
+    let plan = Plan::load(md_file)?;
+    let mut unchanged = true;
+    for path in std::iter::once(md_file).chain(plan.sub_files().iter().map(|f| f.path.as_path())) {
+        let changes = tasks::checkbox_changes(&read_task_file(path)?);
+        if changes.is_empty() {
+            continue;
+        }
+        if !unchanged {
+            println!();
+        }
+        unchanged = false;
+        println!("--- {}", path.display());
+        println!("+++ {} (with checkboxes)", path.display());
+        for change in changes {
+            println!("@@ line {} @@", change.line + 1);
+            println!("-{}", change.before);
+            println!("+{}", change.after);
+        }
+    }
+    if unchanged {
+        println!("None; every list item already has a checkbox.");
+    }
+
+    let normalized = tasks::normalize_checkboxes(&content);
+    let tasks = tasks::parse_tasks(normalized.as_deref().unwrap_or(&content));
+    tasks::validate_overrides(&tasks, settings)?;
+
+    section("Task order");
+    let open: Vec<_> = tasks.iter().filter(|t| !t.checked).collect();
+    if open.is_empty() {
+        println!("No open tasks; the run would end as soon as Claude stops.");
+    }
+    for (position, task) in open.iter().enumerate() {
+        let overrides = task.overrides()?;
+        let note = if overrides.is_empty() {
+            String::new()
+        } else {
+            let effective = settings.with_override(&overrides)?;
+            format!("  (idle timeout {}, loop window {})",
+                settings::format_duration(effective.idle_timeout), effective.loop_window)
+        };
+        println!("{:>3}. [#{} line {}] {}{}", position + 1, task.id, task.line + 1, task.text, note);
+    }
+    let done = tasks.len() - open.len();
+    if done > 0 {
+        println!("({} already completed task(s) skipped)", done);
+    }
+
+    section("Simulated agent session");
+    println!("$ cd {}", settings.working_dir(md_file).display());
+    println!("$ {} {}", settings.agent_command, settings.agent_command_args().join(" "));
+    println!("\n> Initial prompt:\n");
+    for line in Claudia::create_initial_prompt(md_file, settings, "<run-id>")?.lines() {
+        println!("  {}", line);
+    }
+    let vars = Claudia::prompt_vars(md_file, settings, "<run-id>", &|t| t.checked);
+    let render = |name: &str, text: &str| Template::parse(name, text).map(|t| t.render(&vars));
+    println!("\n> When Claude is idle for {} and tasks remain, send: {:?}",
+        settings::format_duration(settings.idle_timeout), render("continue_message", &settings.continue_message)?);
+    if settings.watch_processes {
+        println!("> Unless Claude's processes use {}% CPU or the disk (for up to {})",
+            settings.busy_cpu_percent, settings::format_duration(settings.max_busy_wait));
+    }
+    if !settings.task_prompt.is_empty() {
+        println!("> When Claude has moved on to another task, send instead: {:?}",
+            render("task_prompt", &settings.task_prompt)?);
+    }
+    let ladder: Vec<&str> = settings.escalation.iter().map(|step| step.name()).collect();
+    println!("> While no task gets done, escalate: {} (the last step repeats)", ladder.join(" -> "));
+    if !settings.verify_command.is_empty() {
+        println!("> When every task is checked, run: {}", settings.verify_command);
+    }
+    match settings.suspicious_policy {
+        SuspiciousPolicy::Off => {}
+        policy => println!("> When a task is checked off with no changes or tool use, or {} at once: {}",
+            settings.suspicious_batch,
+            match policy {
+                SuspiciousPolicy::Reopen => "reopen it and challenge Claude",
+                SuspiciousPolicy::Verify => "run the verify command and reopen it if that fails",
+                _ => "warn",
+            }),
+    }
+    if settings.max_restarts > 0 {
+        println!("> If Claude exits with tasks open, restart it{} up to {} times, waiting {} (longer after quick exits)",
+            if settings.restart_resume { " with --continue" } else { "" },
+            settings.max_restarts, settings::format_duration(settings.restart_backoff));
+    }
+    for hook in [Hook::PreRun, Hook::TaskStart, Hook::TaskComplete, Hook::Limit, Hook::Stuck, Hook::PostRun, Hook::Exit] {
+        let command = hook.command(&settings.hooks);
+        if !command.trim().is_empty() {
+            println!("> Hook {}: {}", hook.name(), command);
+        }
+    }
+    for webhook in &settings.webhooks {
+        let events: Vec<&str> = webhook.events.iter().map(|notice| notice.name()).collect();
+        println!("> Notify {} of: {}", webhook.redacted_url(),
+            if events.is_empty() { "all events".to_string() } else { events.join(", ") });
+    }
+    if settings.email.is_enabled() {
+        println!("> Mail a digest to {} through {}", settings.email.to.join(", "), settings.email.smtp_host);
+    }
+    if settings.stall_continues > 0 {
+        println!("> After {} continues without file changes or task progress, treat Claude as stuck", settings.stall_continues);
+    }
+    println!("> Stop after {} continues, or when the last {} responses are alike or cycle (or Claude is stalled) and no clear, restart or fail step is left.",
+        settings.max_continues, settings.loop_window);
+    Ok(0)
+}
+
+/// `claudia run --detach`: start the same run in a new session, with its
+/// output going to a log file, and return once it has registered.
+pub fn detach(md_files: &[PathBuf]) -> Result<i32> {
+    for md_file in md_files {
+        read_task_file(md_file)?;
+    }
+    let logs_dir = state::state_dir().join("logs");
+    fs::create_dir_all(&logs_dir).with_context(|| format!("Failed to create {}", logs_dir.display()))?;
+    let log_path = logs_dir.join(format!("{}-{}.log", Local::now().format("%Y%m%d-%H%M%S"), std::process::id()));
+    let log = fs::File::create(&log_path).with_context(|| format!("Failed to create {}", log_path.display()))?;
+
+    let args: Vec<_> = std::env::args_os().skip(1).filter(|arg| arg != "--detach").collect();
+    let mut cmd = std::process::Command::new(std::env::current_exe()?);
+    cmd.args(args).stdin(Stdio::null()).stdout(log.try_clone()?).stderr(log);
+    // A new session has no controlling terminal, so a dropped SSH connection
+    // (SIGHUP to the terminal's session) doesn't reach the run
+    // SAFETY: the closure runs between fork and exec, where only
+    // async-signal-safe calls are allowed; setsid and reading errno are
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = cmd.spawn().context("Failed to start the detached run")?;
+
+    for _ in 0..100 {
+        if let Some(run) = state::list_runs()?.into_iter().find(|run| run.pid == child.id()) {
+            println!("Run {} started in the background (pid {})", run.run_id, run.pid);
+            println!("Output log: {}", log_path.display());
+            println!("Watch it with `claudia attach {}`, control it with `claudia ctl --run {} ...`",
+                run.run_id, run.run_id);
+            return Ok(0);
+        }
+        if let Some(status) = child.try_wait()? {
+            print!("{}", fs::read_to_string(&log_path).unwrap_or_default());
+            return Ok(status.code().unwrap_or(1));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    println!("Started in the background (pid {}), but no run was recorded yet", child.id());
+    println!("Output log: {}", log_path.display());
+    Ok(0)
+}
+
+const TASK_TEMPLATE: &str = "# Tasks for Claude
+
+- [ ] Describe the first task here
+- [ ] Describe the next task here
+";
+
+/// `claudia init`: scaffold a task file and a commented claudia.toml.
+pub fn init(dir: &Path, file: &str, force: bool) -> Result<i32> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let files = [
+        (dir.join(file), TASK_TEMPLATE.to_string()),
+        (dir.join(CONFIG_FILE_NAME), config_template()),
+    ];
+    for (path, content) in files {
+        if path.exists() && !force {
+            println!("Skipped {} (already exists; use --force to overwrite)", path.display());
+            continue;
+        }
+        fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+        println!("Created {}", path.display());
+    }
+    Ok(0)
+}
+
+/// A claudia.toml listing every setting with its default, commented out.
+fn config_template() -> String {
+    let defaults = toml::Table::try_from(Settings::default()).expect("default settings serialize to a table");
+    let mut out = String::from(
+        "# Claudia configuration. Uncomment a line to change the default.\n\
+         # Profiles go in [profiles.<name>] tables and are selected with --profile.\n\n",
+    );
+    for (key, value) in &defaults {
+        out.push_str(&format!("# {} = {}\n", key, value));
+    }
+    out.push_str("\n# [profiles.overnight]\n# idle_timeout = \"20m\"\n# max_continues = 200\n");
+    out
+}
+
+fn describe_phase(run: &RunState) -> String {
+    if run.is_stale() {
+        return format!("stale (process {} is gone)", run.pid);
+    }
+    match run.phase {
+        Phase::Running => format!("running (pid {})", run.pid),
+        Phase::Waiting => match run.waiting_until {
+            Some(until) => format!("{} until {} (pid {})",
+                run.wait_reason.unwrap_or(WaitReason::UsageLimit).describe(), until.format("%a %H:%M"), run.pid),
+            None => format!("waiting (pid {})", run.pid),
+        },
+        Phase::Finished => match (run.reason, run.exit_code) {
+            (Some(reason), Some(code)) => format!("finished: {} (exit {})", reason.describe(), code),
+            _ => "finished".to_string(),
+        },
+    }
+}
+
+/// `claudia status`: show one run, or all runs with `--all`.
+pub fn status(query: Option<&str>, all: bool, json: bool) -> Result<i32> {
+    if all {
+        let runs = state::list_runs()?;
+        if json {
+            println!("{}", serde_json::to_string_pretty(&runs)?);
+            return Ok(0);
+        }
+        if runs.is_empty() {
+            println!("No runs recorded in {}", state::runs_dir().display());
+        }
+        for run in &runs {
+            println!("{}  {:>3}/{:<3} {}  {}",
+                run.run_id, run.tasks_completed, run.tasks_total,
+                describe_phase(run), run.md_file.display());
+        }
+        return Ok(0);
+    }
+
+    let run = state::load_state(&state::find_run(query)?)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&run)?);
+        return Ok(0);
+    }
+    print_run(&run);
+    Ok(0)
+}
+
+fn print_run(run: &RunState) {
+    let elapsed = (run.updated_at - run.started_at).num_seconds().max(0) as u64;
+    println!("Run:        {}", run.run_id);
+    println!("Task file:  {}", run.md_file.display());
+    println!("State:      {}", describe_phase(run));
+    println!("Started:    {}", run.started_at.format("%Y-%m-%d %H:%M:%S"));
+    println!("Updated:    {} ({} ago)", run.updated_at.format("%Y-%m-%d %H:%M:%S"),
+        report::format_elapsed((Local::now() - run.updated_at).num_seconds().max(0) as u64));
+    println!("Duration:   {}", report::format_elapsed(elapsed));
+    println!("Status:     {}", run.status);
+    println!("Tasks:      {}/{} completed", run.tasks_completed, run.tasks_total);
+    println!("Continues:  {}", run.continues);
+    if run.paused {
+        println!("Paused:     yes (resume with `claudia ctl resume`)");
+    }
+}
+
+/// `claudia ctl`: send a request to a run's control socket.
+pub fn ctl(query: Option<&str>, request: &Request) -> Result<i32> {
+    let run_dir = state::find_run(query)?;
+    let response = control::send(&run_dir, request)?;
+    match &response.state {
+        Some(run) => print_run(run),
+        None => println!("{}", response.message),
+    }
+    Ok(if response.ok { 0 } else { 1 })
+}
+
+/// `claudia report`: render the Markdown report of a run.
+pub fn report(query: Option<&str>, output: Option<&Path>) -> Result<i32> {
+    let run_dir = state::find_run(query)?;
+    let run = state::load_state(&run_dir)?;
+    let events = state::load_events(&run_dir)?;
+    let rendered = report::render(&run, &events);
+
+    match output {
+        Some(path) => {
+            fs::write(path, rendered).with_context(|| format!("Failed to write {}", path.display()))?;
+            println!("Wrote report to {}", path.display());
+        }
+        None => print!("{}", rendered),
+    }
+    Ok(0)
+}
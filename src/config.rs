@@ -0,0 +1,221 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::notify;
+use crate::settings::{Layer, Resolved, Settings};
+use crate::{tasks, ConfigError};
+
+/// Name of the project config file looked up next to the task file.
+pub const CONFIG_FILE_NAME: &str = "claudia.toml";
+
+/// User-level config directory (`$XDG_CONFIG_HOME/claudia` or `~/.config/claudia`).
+pub fn user_config_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir).join("claudia"));
+    }
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("claudia"))
+}
+
+/// Config files that apply to a task file in `start_dir`, lowest precedence
+/// first: the user-level file, then `claudia.toml` from the filesystem root
+/// down to `start_dir`.
+pub fn discover(start_dir: &Path) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    let start_dir = start_dir.canonicalize().unwrap_or_else(|_| start_dir.to_path_buf());
+    for dir in start_dir.ancestors() {
+        let candidate = dir.join(CONFIG_FILE_NAME);
+        if candidate.is_file() {
+            found.push(candidate);
+        }
+    }
+
+    if let Some(user_file) = user_config_dir().map(|d| d.join(CONFIG_FILE_NAME)) {
+        if user_file.is_file() && !found.contains(&user_file) {
+            found.push(user_file);
+        }
+    }
+
+    found.reverse();
+    found
+}
+
+/// Read one config file into its top-level layer plus the layer for
+/// `profile`, if the file defines it.
+fn load_file(path: &Path, profile: Option<&str>) -> Result<(Layer, Option<Layer>)> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    let mut values: toml::Table = toml::from_str(&content)
+        .map_err(|e| ConfigError(format!("Invalid config file {}: {}", path.display(), e)))?;
+
+    let profiles = match values.remove("profiles") {
+        Some(toml::Value::Table(profiles)) => profiles,
+        Some(_) => {
+            return Err(ConfigError(format!(
+                "Invalid config file {}: 'profiles' must be a table", path.display()
+            )).into())
+        }
+        None => toml::Table::new(),
+    };
+
+    let base = Layer { source: path.display().to_string(), values };
+    let selected = match profile.and_then(|name| profiles.get(name).map(|v| (name, v))) {
+        Some((name, toml::Value::Table(values))) => Some(Layer {
+            source: format!("{} [profiles.{}]", path.display(), name),
+            values: values.clone(),
+        }),
+        Some((name, _)) => {
+            return Err(ConfigError(format!(
+                "Invalid config file {}: profile '{}' must be a table", path.display(), name
+            )).into())
+        }
+        None => None,
+    };
+    Ok((base, selected))
+}
+
+/// Settings from the YAML front matter of a task file, if it has any.
+/// They use the same keys as the config file.
+pub fn front_matter(md_file: &Path) -> Result<Option<Layer>> {
+    let Ok(content) = fs::read_to_string(md_file) else {
+        return Ok(None);
+    };
+    let Some(yaml) = tasks::front_matter(&content).filter(|yaml| !yaml.trim().is_empty()) else {
+        return Ok(None);
+    };
+    let values: toml::Table = serde_yaml::from_str(&yaml)
+        .map_err(|e| ConfigError(format!("Invalid front matter in {}: {}", md_file.display(), e)))?;
+    Ok(Some(Layer { source: format!("{} front matter", md_file.display()), values }))
+}
+
+/// Where settings for a run come from, in addition to the defaults.
+#[derive(Debug, Default)]
+pub struct ConfigSources {
+    /// Directory the config file search starts from (the task file's directory)
+    pub start_dir: PathBuf,
+    /// Task file whose front matter applies
+    pub task_file: Option<PathBuf>,
+    /// Explicit config file from `--config`
+    pub explicit: Option<PathBuf>,
+    /// Profile selected with `--profile`
+    pub profile: Option<String>,
+    /// Values given on the command line
+    pub cli: Option<Layer>,
+}
+
+impl ConfigSources {
+    /// All layers in order of increasing precedence.
+    pub fn layers(&self) -> Result<Vec<Layer>> {
+        let mut files = discover(&self.start_dir);
+        if let Some(explicit) = &self.explicit {
+            if !explicit.is_file() {
+                return Err(ConfigError(format!("Config file '{}' not found", explicit.display())).into());
+            }
+            files.push(explicit.clone());
+        }
+
+        let mut layers = Vec::new();
+        let mut profile_found = false;
+        for file in &files {
+            let (base, profile) = load_file(file, self.profile.as_deref())?;
+            layers.push(base);
+            if let Some(profile) = profile {
+                profile_found = true;
+                layers.push(profile);
+            }
+        }
+
+        if let Some(name) = &self.profile {
+            if !profile_found {
+                return Err(ConfigError(format!("Profile '{}' is not defined in any config file", name)).into());
+            }
+        }
+
+        if let Some(task_file) = &self.task_file {
+            layers.extend(front_matter(task_file)?);
+        }
+        layers.extend(self.cli.clone());
+        Ok(layers)
+    }
+
+    pub fn resolve(&self) -> Result<Resolved> {
+        Ok(Settings::resolve(&self.layers()?)?)
+    }
+}
+
+/// Shown instead of secrets.
+const REDACTED: &str = "***";
+
+/// `values` with secrets replaced, fit for printing.
+fn redacted(values: &toml::Table) -> toml::Table {
+    let mut values = values.clone();
+    if let Some(toml::Value::Table(email)) = values.get_mut("email") {
+        if email.get("password").and_then(toml::Value::as_str).is_some_and(|p| !p.is_empty()) {
+            email.insert("password".to_string(), REDACTED.into());
+        }
+    }
+    if let Some(toml::Value::Array(webhooks)) = values.get_mut("webhooks") {
+        for webhook in webhooks.iter_mut().filter_map(toml::Value::as_table_mut) {
+            if let Some(url) = webhook.get("url").and_then(toml::Value::as_str) {
+                let url = notify::redact_url(url);
+                webhook.insert("url".to_string(), url.into());
+            }
+            if let Some(toml::Value::Table(headers)) = webhook.get_mut("headers") {
+                for (_, value) in headers.iter_mut() {
+                    *value = REDACTED.into();
+                }
+            }
+        }
+    }
+    values
+}
+
+/// Print the effective configuration with the source of each value.
+/// Secrets such as passwords are redacted.
+pub fn show(resolved: &Resolved) {
+    let values = redacted(&resolved.values);
+    let width = values
+        .iter()
+        .map(|(k, v)| k.len() + v.to_string().len() + 3)
+        .max()
+        .unwrap_or(0);
+    for (key, value) in &values {
+        let line = format!("{} = {}", key, value);
+        let source = resolved.sources.get(key).map(String::as_str).unwrap_or("default");
+        println!("{:<width$}  # {}", line, source, width = width);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(toml: &str) -> toml::Table {
+        toml.parse().unwrap()
+    }
+
+    #[test]
+    fn redacts_email_password() {
+        let shown = redacted(&values("[email]\nsmtp_host = \"mail\"\npassword = \"hunter2\"\n"));
+        assert_eq!(shown["email"]["password"].as_str(), Some(REDACTED));
+        assert_eq!(shown["email"]["smtp_host"].as_str(), Some("mail"));
+    }
+
+    #[test]
+    fn redacts_webhook_headers_and_url_path() {
+        let shown = redacted(&values(concat!(
+            "[[webhooks]]\n",
+            "url = \"https://hooks.slack.com/services/T000/B000/XXXX\"\n",
+            "headers = { Authorization = \"Bearer secret\" }\n",
+        )));
+        let webhook = &shown["webhooks"][0];
+        assert_eq!(webhook["url"].as_str(), Some("https://hooks.slack.com/***"));
+        assert_eq!(webhook["headers"]["Authorization"].as_str(), Some(REDACTED));
+    }
+
+    #[test]
+    fn leaves_empty_password_alone() {
+        let shown = redacted(&values("[email]\npassword = \"\"\n"));
+        assert_eq!(shown["email"]["password"].as_str(), Some(""));
+    }
+}
@@ -0,0 +1,338 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::state::{EventKind, RunRecord, RunState};
+use crate::tasks::{self, Task};
+use crate::debug_enabled;
+
+/// File name of the control socket inside a run directory.
+pub const SOCKET_NAME: &str = "control.sock";
+
+/// A request sent to a running claudia, one JSON object per line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    Status,
+    Pause,
+    Resume,
+    Inject { message: String },
+    SkipTask { task: usize },
+    StopAfterCurrent,
+    Abort,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<RunState>,
+}
+
+impl Response {
+    fn ok(message: impl Into<String>) -> Self {
+        Self { ok: true, message: message.into(), state: None }
+    }
+
+    fn error(message: impl Into<String>) -> Self {
+        Self { ok: false, message: message.into(), state: None }
+    }
+}
+
+/// Instructions received over the socket, picked up by the monitoring loop.
+#[derive(Debug, Default)]
+pub struct Control {
+    /// Don't send Continues while set
+    pub paused: Mutex<bool>,
+    /// Messages to type into Claude, oldest first
+    pub inject: Mutex<VecDeque<String>>,
+    /// Texts of tasks to treat as done without Claude checking them
+    pub skipped: Mutex<Vec<String>>,
+    /// Texts of tasks given up on by the `fail` escalation step
+    pub failed: Mutex<Vec<String>>,
+    /// Text of the task after whose completion the run should stop
+    pub stop_after: Mutex<Option<String>>,
+    pub abort: Mutex<bool>,
+}
+
+impl Control {
+    /// Whether a task is checked off, was skipped or was given up on by the
+    /// escalation ladder.
+    pub fn is_done(&self, task: &Task) -> bool {
+        task.checked
+            || self.skipped.lock().unwrap().contains(&task.text)
+            || self.failed.lock().unwrap().contains(&task.text)
+    }
+}
+
+/// Serves the control socket of a run until dropped.
+pub struct ControlServer {
+    path: PathBuf,
+    shutdown: Arc<Mutex<bool>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl ControlServer {
+    pub fn start(record: Arc<RunRecord>, control: Arc<Control>, md_file: PathBuf) -> Result<Self> {
+        let path = record.dir().join(SOCKET_NAME);
+        // A socket left over from a crashed run would make bind fail
+        fs::remove_file(&path).ok();
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("Failed to create control socket {}", path.display()))?;
+        // Poll instead of blocking in accept so the thread can be stopped
+        listener.set_nonblocking(true)?;
+
+        let shutdown = Arc::new(Mutex::new(false));
+        let shutdown_clone = Arc::clone(&shutdown);
+        let thread = thread::spawn(move || {
+            while !*shutdown_clone.lock().unwrap() {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        if let Err(e) = serve(stream, &record, &control, &md_file) {
+                            if debug_enabled() {
+                                eprintln!("[DEBUG] Control connection failed: {}", e);
+                            }
+                        }
+                    }
+                    Err(_) => thread::sleep(Duration::from_millis(100)),
+                }
+            }
+        });
+
+        Ok(Self { path, shutdown, thread: Some(thread) })
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        *self.shutdown.lock().unwrap() = true;
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+fn serve(stream: UnixStream, record: &RunRecord, control: &Control, md_file: &Path) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    let response = match serde_json::from_str::<Request>(&line) {
+        Ok(request) => handle(request, record, control, md_file),
+        Err(e) => Response::error(format!("Invalid request: {}", e)),
+    };
+    let mut stream = stream;
+    writeln!(stream, "{}", serde_json::to_string(&response)?)?;
+    Ok(())
+}
+
+fn handle(request: Request, record: &RunRecord, control: &Control, md_file: &Path) -> Response {
+    if !matches!(request, Request::Status) {
+        if let Ok(command) = serde_json::to_string(&request) {
+            record.event(EventKind::ControlCommand { command });
+        }
+    }
+    match request {
+        Request::Status => Response { state: Some(record.state()), ..Response::ok("ok") },
+        Request::Pause => {
+            *control.paused.lock().unwrap() = true;
+            record.update(|state| state.paused = true);
+            Response::ok("Paused: no Continues will be sent until resumed")
+        }
+        Request::Resume => {
+            *control.paused.lock().unwrap() = false;
+            record.update(|state| state.paused = false);
+            Response::ok("Resumed")
+        }
+        Request::Inject { message } => {
+            if message.trim().is_empty() {
+                return Response::error("Message is empty");
+            }
+            control.inject.lock().unwrap().push_back(message);
+            Response::ok("Message queued; it will be sent to Claude shortly")
+        }
+        Request::SkipTask { task } => {
+            let content = fs::read_to_string(md_file).unwrap_or_default();
+            match tasks::parse_tasks(&content).into_iter().find(|t| t.id == task) {
+                Some(t) if t.checked => Response::error(format!("Task {} is already completed", task)),
+                Some(t) => {
+                    control.skipped.lock().unwrap().push(t.text.clone());
+                    control.inject.lock().unwrap().push_back(format!(
+                        "Skip this task and move on to the next one: {}", t.text
+                    ));
+                    Response::ok(format!("Skipping task {}: {}", task, t.text))
+                }
+                None => Response::error(format!("No task with ID {}", task)),
+            }
+        }
+        Request::StopAfterCurrent => {
+            let content = fs::read_to_string(md_file).unwrap_or_default();
+            // The task the run is on: skipped and failed tasks count as done
+            match tasks::parse_tasks(&content).into_iter().find(|t| !control.is_done(t)) {
+                Some(task) => {
+                    *control.stop_after.lock().unwrap() = Some(task.text.clone());
+                    Response::ok(format!("Will stop after task {}: {}", task.id, task.text))
+                }
+                None => Response::error("There is no open task"),
+            }
+        }
+        Request::Abort => {
+            *control.abort.lock().unwrap() = true;
+            Response::ok("Aborting the run")
+        }
+    }
+}
+
+/// Send one request to the run in `run_dir` and return its response.
+pub fn send(run_dir: &Path, request: &Request) -> Result<Response> {
+    let path = run_dir.join(SOCKET_NAME);
+    let mut stream = UnixStream::connect(&path)
+        .with_context(|| format!("Failed to connect to {} (is the run still active?)", path.display()))?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    writeln!(stream, "{}", serde_json::to_string(request)?)?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    serde_json::from_str(&line).context("Invalid response from the run")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state;
+
+    /// A run of `content` in a fresh directory named after `name`.
+    fn run(name: &str, content: &str) -> (PathBuf, RunRecord) {
+        let dir = std::env::temp_dir().join(format!("claudia-control-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        let md_file = dir.join("tasks.md");
+        fs::write(&md_file, content).unwrap();
+        let record = RunRecord::create_in(&dir.join("runs"), &md_file, &dir).unwrap();
+        (md_file, record)
+    }
+
+    fn clean_up(md_file: &Path) {
+        fs::remove_dir_all(md_file.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn pause_and_resume_update_control_and_state() {
+        let (md_file, record) = run("pause", "- [ ] One\n");
+        let control = Control::default();
+        assert!(handle(Request::Pause, &record, &control, &md_file).ok);
+        assert!(*control.paused.lock().unwrap());
+        assert!(record.state().paused);
+        assert!(handle(Request::Resume, &record, &control, &md_file).ok);
+        assert!(!*control.paused.lock().unwrap());
+        assert!(!record.state().paused);
+        clean_up(&md_file);
+    }
+
+    #[test]
+    fn inject_queues_messages_but_not_empty_ones() {
+        let (md_file, record) = run("inject", "- [ ] One\n");
+        let control = Control::default();
+        let response = handle(Request::Inject { message: "  ".to_string() }, &record, &control, &md_file);
+        assert!(!response.ok);
+        assert_eq!(response.message, "Message is empty");
+        for message in ["Use the new API", "Then rerun the tests"] {
+            assert!(handle(Request::Inject { message: message.to_string() }, &record, &control, &md_file).ok);
+        }
+        assert_eq!(*control.inject.lock().unwrap(), ["Use the new API", "Then rerun the tests"]);
+        clean_up(&md_file);
+    }
+
+    #[test]
+    fn abort_sets_the_flag() {
+        let (md_file, record) = run("abort", "- [ ] One\n");
+        let control = Control::default();
+        assert!(handle(Request::Abort, &record, &control, &md_file).ok);
+        assert!(*control.abort.lock().unwrap());
+        clean_up(&md_file);
+    }
+
+    #[test]
+    fn status_returns_the_state_and_is_not_logged() {
+        let (md_file, record) = run("status", "- [ ] One\n");
+        let control = Control::default();
+        let response = handle(Request::Status, &record, &control, &md_file);
+        assert!(response.ok);
+        assert_eq!(response.state.map(|s| s.run_id), Some(record.run_id()));
+        handle(Request::Pause, &record, &control, &md_file);
+        let commands: Vec<String> = state::load_events(record.dir())
+            .unwrap()
+            .into_iter()
+            .filter_map(|event| match event.kind {
+                EventKind::ControlCommand { command } => Some(command),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(commands, [r#"{"command":"pause"}"#]);
+        clean_up(&md_file);
+    }
+
+    #[test]
+    fn skip_task_rejects_done_and_unknown_tasks() {
+        let (md_file, record) = run("skip", "- [x] One\n- [ ] Two\n");
+        let control = Control::default();
+        let response = handle(Request::SkipTask { task: 1 }, &record, &control, &md_file);
+        assert_eq!((response.ok, response.message.as_str()), (false, "Task 1 is already completed"));
+        let response = handle(Request::SkipTask { task: 9 }, &record, &control, &md_file);
+        assert_eq!((response.ok, response.message.as_str()), (false, "No task with ID 9"));
+        assert!(handle(Request::SkipTask { task: 2 }, &record, &control, &md_file).ok);
+        assert_eq!(*control.skipped.lock().unwrap(), ["Two"]);
+        assert_eq!(control.inject.lock().unwrap().len(), 1);
+        clean_up(&md_file);
+    }
+
+    #[test]
+    fn stop_after_current_passes_over_skipped_and_failed_tasks() {
+        let (md_file, record) = run("stop", "- [x] One\n- [ ] Two\n- [ ] Three\n- [ ] Four\n");
+        let control = Control::default();
+
+        let response = handle(Request::SkipTask { task: 2 }, &record, &control, &md_file);
+        assert!(response.ok, "{}", response.message);
+        control.failed.lock().unwrap().push("Three".to_string());
+
+        let response = handle(Request::StopAfterCurrent, &record, &control, &md_file);
+        assert!(response.ok, "{}", response.message);
+        assert_eq!(control.stop_after.lock().unwrap().as_deref(), Some("Four"));
+
+        control.skipped.lock().unwrap().push("Four".to_string());
+        assert!(!handle(Request::StopAfterCurrent, &record, &control, &md_file).ok);
+        clean_up(&md_file);
+    }
+
+    #[test]
+    fn serve_answers_requests_and_rejects_unknown_ones() {
+        let (md_file, record) = run("serve", "- [ ] One\n");
+        let control = Control::default();
+        for (request, ok, message) in [
+            (r#"{"command":"abort"}"#, true, "Aborting the run"),
+            (r#"{"command":"reboot"}"#, false, "Invalid request: unknown variant `reboot`"),
+            ("not json", false, "Invalid request: "),
+        ] {
+            let (ours, mut theirs) = UnixStream::pair().unwrap();
+            writeln!(theirs, "{}", request).unwrap();
+            serve(ours, &record, &control, &md_file).unwrap();
+            let mut line = String::new();
+            BufReader::new(&theirs).read_line(&mut line).unwrap();
+            let response: Response = serde_json::from_str(&line).unwrap();
+            assert_eq!(response.ok, ok, "{}", request);
+            assert!(response.message.starts_with(message), "{}", response.message);
+        }
+        assert!(*control.abort.lock().unwrap());
+        clean_up(&md_file);
+    }
+}
@@ -0,0 +1,364 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::{digest, state};
+use crate::{digest_email, run_task_file, ConfigError, ExitReason, RunSummary, SettingsArgs};
+
+/// Default queue location: `<state dir>/queue`.
+pub fn default_queue_dir() -> PathBuf {
+    state::state_dir().join("queue")
+}
+
+/// A task file submitted to the daemon.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub job_id: String,
+    pub md_file: PathBuf,
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub config: Option<PathBuf>,
+    pub submitted_at: DateTime<Local>,
+}
+
+/// Outcome of a processed job, kept in `done/`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobResult {
+    #[serde(flatten)]
+    pub job: Job,
+    pub started_at: DateTime<Local>,
+    pub finished_at: DateTime<Local>,
+    pub summary: RunSummary,
+}
+
+/// Queue directory layout: `pending/`, `running/` and `done/` hold one JSON
+/// file per job, named after the job ID so they sort in submission order.
+struct Queue {
+    dir: PathBuf,
+}
+
+impl Queue {
+    fn open(dir: &Path) -> Result<Self> {
+        for sub in ["pending", "running", "done"] {
+            fs::create_dir_all(dir.join(sub))
+                .with_context(|| format!("Failed to create queue directory {}", dir.join(sub).display()))?;
+        }
+        Ok(Self { dir: dir.to_path_buf() })
+    }
+
+    fn path(&self, sub: &str, job_id: &str) -> PathBuf {
+        self.dir.join(sub).join(format!("{}.json", job_id))
+    }
+
+    /// Job files in `sub`, oldest first.
+    fn entries(&self, sub: &str) -> Result<Vec<PathBuf>> {
+        let dir = self.dir.join(sub);
+        let mut entries: Vec<PathBuf> = fs::read_dir(&dir)
+            .with_context(|| format!("Failed to read {}", dir.display()))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        entries.sort();
+        Ok(entries)
+    }
+
+    /// Take the oldest pending job by moving it to `running/`. Job files that
+    /// can't be read are set aside with the extension `.invalid`.
+    fn claim(&self) -> Result<Option<(PathBuf, Job)>> {
+        for next in self.entries("pending")? {
+            // Skip a job that vanished in the meantime, e.g. claimed by hand
+            let running_path = self.dir.join("running").join(next.file_name().unwrap_or_default());
+            if fs::rename(&next, &running_path).is_err() {
+                continue;
+            }
+            match read_json(&running_path) {
+                Ok(job) => return Ok(Some((running_path, job))),
+                Err(e) => {
+                    eprintln!("[WARN] Skipping {}: {:#}", running_path.display(), e);
+                    fs::rename(&running_path, running_path.with_extension("invalid")).ok();
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Record the result of a job claimed from `running_path` in `done/`.
+    fn finish(&self, running_path: &Path, result: &JobResult) -> Result<()> {
+        self.write(&self.path("done", &result.job.job_id), result)?;
+        fs::remove_file(running_path).ok();
+        Ok(())
+    }
+
+    /// Put jobs left in `running/` by a daemon that died back in line.
+    fn requeue_running(&self) -> Result<()> {
+        for path in self.entries("running")? {
+            if let Some(name) = path.file_name() {
+                fs::rename(&path, self.dir.join("pending").join(name)).ok();
+            }
+        }
+        Ok(())
+    }
+
+    /// Lock the queue for this process, so only one daemon works on it. The
+    /// lock lasts until the returned file is closed; the file holds our pid.
+    fn lock(&self) -> Result<File> {
+        let path = self.dir.join("daemon.pid");
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        // SAFETY: flock only takes the descriptor, which `file` keeps open
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let error = std::io::Error::last_os_error();
+            if error.raw_os_error() != Some(libc::EWOULDBLOCK) {
+                return Err(error).with_context(|| format!("Failed to lock {}", path.display()));
+            }
+            let pid = fs::read_to_string(&path).unwrap_or_default();
+            return Err(ConfigError(format!(
+                "Another daemon (pid {}) is already processing {}", pid.trim(), self.dir.display()
+            )).into());
+        }
+        file.set_len(0)?;
+        write!(file, "{}", std::process::id()).with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(file)
+    }
+
+    fn write<T: Serialize>(&self, path: &Path, value: &T) -> Result<()> {
+        // Write to a temporary file and rename so the daemon never reads a partial job
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(value)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, path).with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T> {
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&content).with_context(|| format!("Invalid job file {}", path.display()))
+}
+
+/// `claudia submit`: add task files to the queue.
+pub fn submit(md_files: &[PathBuf], profile: Option<&str>, config: Option<&Path>, queue_dir: &Path) -> Result<i32> {
+    let queue = Queue::open(queue_dir)?;
+    let config = config
+        .map(|c| c.canonicalize().map_err(|_| ConfigError(format!("Config file '{}' not found", c.display()))))
+        .transpose()?;
+
+    // One time for the batch, and the position in it padded, so the jobs sort in the order given
+    let now = Local::now();
+    for (i, md_file) in md_files.iter().enumerate() {
+        let md_file = md_file
+            .canonicalize()
+            .map_err(|_| ConfigError(format!("File '{}' not found", md_file.display())))?;
+        let job = Job {
+            job_id: format!("{}-{}-{:04}", now.format("%Y%m%d-%H%M%S%3f"), std::process::id(), i),
+            md_file,
+            profile: profile.map(str::to_string),
+            config: config.clone(),
+            submitted_at: now,
+        };
+        queue.write(&queue.path("pending", &job.job_id), &job)?;
+        println!("Queued {} as job {}", job.md_file.display(), job.job_id);
+    }
+    Ok(0)
+}
+
+/// `claudia queue`: show pending, running and finished jobs.
+pub fn list(queue_dir: &Path, json: bool) -> Result<i32> {
+    let queue = Queue::open(queue_dir)?;
+    let pending: Vec<Job> = queue.entries("pending")?.iter().filter_map(|p| read_json(p).ok()).collect();
+    let running: Vec<Job> = queue.entries("running")?.iter().filter_map(|p| read_json(p).ok()).collect();
+    let done: Vec<JobResult> = queue.entries("done")?.iter().filter_map(|p| read_json(p).ok()).collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({
+            "pending": pending,
+            "running": running,
+            "done": done,
+        }))?);
+        return Ok(0);
+    }
+
+    for job in &running {
+        println!("{}  running   {}", job.job_id, job.md_file.display());
+    }
+    for job in &pending {
+        println!("{}  pending   {}", job.job_id, job.md_file.display());
+    }
+    for result in &done {
+        let summary = &result.summary;
+        println!("{}  {:<9} {}  ({}/{} tasks, exit {}{})",
+            result.job.job_id,
+            if summary.reason == ExitReason::AllDone { "done" } else { "failed" },
+            result.job.md_file.display(),
+            summary.tasks_completed, summary.tasks_total, summary.exit_code,
+            summary.run_id.as_deref().map(|id| format!(", run {}", id)).unwrap_or_default());
+    }
+    if running.is_empty() && pending.is_empty() && done.is_empty() {
+        println!("Queue {} is empty", queue_dir.display());
+    }
+    Ok(0)
+}
+
+/// Sleep for `duration`, waking early if interrupted.
+fn sleep_interruptible(duration: Duration, interrupted: &Mutex<bool>) {
+    let mut remaining = duration;
+    while !remaining.is_zero() && !*interrupted.lock().unwrap() {
+        let step = remaining.min(Duration::from_secs(1));
+        thread::sleep(step);
+        remaining -= step;
+    }
+}
+
+/// `claudia daemon`: process queued task files one after another.
+pub fn run(queue_dir: &Path, once: bool, poll_interval: Duration, interrupted: Arc<Mutex<bool>>) -> Result<i32> {
+    let queue = Queue::open(queue_dir)?;
+    let lock = queue.lock()?;
+    queue.requeue_running()?;
+
+    println!("Claudia daemon watching {}", queue_dir.display());
+    let mut processed = 0;
+    // Runs since the queue last ran empty, for the digest
+    let mut finished = Vec::new();
+    while !*interrupted.lock().unwrap() {
+        let Some((running_path, job)) = queue.claim()? else {
+            digest::send_all(std::mem::take(&mut finished));
+            if once {
+                break;
+            }
+            sleep_interruptible(poll_interval, &interrupted);
+            continue;
+        };
+
+
+        println!("\n▶ Job {}: {}", job.job_id, job.md_file.display());
+        let started_at = Local::now();
+        let settings = SettingsArgs {
+            config: job.config.clone(),
+            profile: job.profile.clone(),
+            ..Default::default()
+        };
+        let summary = run_task_file(&job.md_file, &settings, None, Arc::clone(&interrupted))
+            .unwrap_or_else(|e| {
+                eprintln!("Error: {:#}", e);
+                RunSummary::from_error(&job.md_file, &e)
+            });
+        println!("■ Job {} finished: {} (exit {})", job.job_id, summary.reason.describe(), summary.exit_code);
+
+        if let Some(email) = digest_email(&job.md_file, &settings) {
+            finished.push((email, summary.clone()));
+        }
+        let result = JobResult { job: job.clone(), started_at, finished_at: Local::now(), summary };
+        queue.finish(&running_path, &result)?;
+        processed += 1;
+    }
+
+    digest::send_all(finished);
+    // The file stays: removing it could let a daemon that opened it meanwhile
+    // lock a file no one else sees
+    lock.set_len(0).ok();
+    drop(lock);
+    println!("Claudia daemon stopped after {} job(s)", processed);
+    Ok(if *interrupted.lock().unwrap() { ExitReason::Interrupted.code() } else { 0 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty queue and `count` task files in a fresh directory named after `name`.
+    fn setup(name: &str, count: usize) -> (PathBuf, Queue, Vec<PathBuf>) {
+        let dir = std::env::temp_dir().join(format!("claudia-daemon-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        let md_files: Vec<PathBuf> = (0..count)
+            .map(|i| {
+                let path = dir.join(format!("task-{}.md", i));
+                fs::write(&path, "- [ ] Task\n").unwrap();
+                path.canonicalize().unwrap()
+            })
+            .collect();
+        let queue = Queue::open(&dir.join("queue")).unwrap();
+        (dir, queue, md_files)
+    }
+
+    fn result(job: Job) -> JobResult {
+        let summary = RunSummary::from_error(&job.md_file, &anyhow::anyhow!("agent not found"));
+        JobResult { job, started_at: Local::now(), finished_at: Local::now(), summary }
+    }
+
+    #[test]
+    fn claims_jobs_in_submission_order() {
+        let (dir, queue, md_files) = setup("order", 12);
+        submit(&md_files[..11], Some("night"), None, &queue.dir).unwrap();
+        // A later `claudia submit`, at least a millisecond on
+        thread::sleep(Duration::from_millis(2));
+        submit(&md_files[11..], None, None, &queue.dir).unwrap();
+
+        let mut claimed = Vec::new();
+        while let Some((running_path, job)) = queue.claim().unwrap() {
+            assert!(running_path.starts_with(queue.dir.join("running")));
+            assert_eq!(queue.entries("running").unwrap(), std::slice::from_ref(&running_path));
+            claimed.push(job.md_file.clone());
+            queue.finish(&running_path, &result(job)).unwrap();
+        }
+        assert_eq!(claimed, md_files);
+        assert!(queue.entries("pending").unwrap().is_empty());
+        assert!(queue.entries("running").unwrap().is_empty());
+        let done: Vec<JobResult> = queue.entries("done").unwrap().iter().map(|p| read_json(p).unwrap()).collect();
+        assert_eq!(done.len(), 12);
+        assert_eq!(done[0].job.profile.as_deref(), Some("night"));
+        assert_eq!(done[0].summary.error.as_deref(), Some("agent not found"));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn submit_rejects_missing_files() {
+        let (dir, queue, _) = setup("missing", 0);
+        assert!(submit(&[dir.join("nope.md")], None, None, &queue.dir).is_err());
+        assert!(queue.entries("pending").unwrap().is_empty());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn requeues_jobs_left_running_and_sets_aside_invalid_ones() {
+        let (dir, queue, md_files) = setup("requeue", 2);
+        submit(&md_files, None, None, &queue.dir).unwrap();
+        let (_, first) = queue.claim().unwrap().unwrap();
+        // The daemon died while running the first job
+        queue.requeue_running().unwrap();
+        assert_eq!(queue.entries("pending").unwrap().len(), 2);
+        assert_eq!(queue.claim().unwrap().unwrap().1.job_id, first.job_id);
+
+        fs::write(queue.dir.join("pending").join("0-broken.json"), "{").unwrap();
+        let (_, job) = queue.claim().unwrap().unwrap();
+        assert_eq!(job.md_file, md_files[1]);
+        assert!(queue.dir.join("running").join("0-broken.invalid").exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn only_one_daemon_locks_a_queue() {
+        let (dir, queue, _) = setup("lock", 0);
+        let lock = queue.lock().unwrap();
+        let pid = std::process::id().to_string();
+        assert_eq!(fs::read_to_string(queue.dir.join("daemon.pid")).unwrap(), pid);
+        let error = queue.lock().unwrap_err();
+        assert!(error.to_string().starts_with(&format!("Another daemon (pid {}) is already processing", pid)), "{}", error);
+        drop(lock);
+        assert!(queue.lock().is_ok());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,296 @@
+use anyhow::{Context, Result};
+use chrono::Local;
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::fs;
+use std::time::Duration;
+
+use crate::git;
+use crate::report::{self, format_elapsed};
+use crate::state::{self, EventKind};
+use crate::{ConfigError, RunSummary};
+
+/// How the connection to the SMTP server is secured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// Plain connection upgraded with STARTTLS (usually port 587)
+    #[default]
+    Starttls,
+    /// TLS from the start (usually port 465)
+    Tls,
+    /// No encryption, e.g. for a local relay
+    None,
+}
+
+/// Where to send the digest mailed at the end of a run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Email {
+    /// SMTP server; empty sends no digest
+    pub smtp_host: String,
+    /// 0 uses the usual port for `security`
+    pub smtp_port: u16,
+    pub security: SmtpSecurity,
+    /// Login for the SMTP server; empty sends without authentication
+    pub username: String,
+    pub password: String,
+    /// Environment variable holding the password, to keep it out of config files
+    pub password_env: String,
+    pub from: String,
+    pub to: Vec<String>,
+    /// Attach each run's Markdown report
+    pub attach_report: bool,
+}
+
+impl Default for Email {
+    fn default() -> Self {
+        Self {
+            smtp_host: String::new(),
+            smtp_port: 0,
+            security: SmtpSecurity::default(),
+            username: String::new(),
+            password: String::new(),
+            password_env: String::new(),
+            from: String::new(),
+            to: Vec::new(),
+            attach_report: true,
+        }
+    }
+}
+
+impl Email {
+    pub fn is_enabled(&self) -> bool {
+        !self.smtp_host.is_empty()
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let mailbox = |address: &str| {
+            address.parse::<Mailbox>().map_err(|e| ConfigError(format!("Invalid email address '{}': {}", address, e)))
+        };
+        if self.from.is_empty() || self.to.is_empty() {
+            return Err(ConfigError("email needs 'from' and 'to' when 'smtp_host' is set".to_string()));
+        }
+        mailbox(&self.from)?;
+        for address in &self.to {
+            mailbox(address)?;
+        }
+        Ok(())
+    }
+
+    fn port(&self) -> u16 {
+        match (self.smtp_port, self.security) {
+            (0, SmtpSecurity::Starttls) => 587,
+            (0, SmtpSecurity::Tls) => 465,
+            (0, SmtpSecurity::None) => 25,
+            (port, _) => port,
+        }
+    }
+
+    fn password(&self) -> Result<String> {
+        if self.password_env.is_empty() {
+            return Ok(self.password.clone());
+        }
+        std::env::var(&self.password_env)
+            .with_context(|| format!("Environment variable {} (email.password_env) is not set", self.password_env))
+    }
+
+    fn transport(&self) -> Result<SmtpTransport> {
+        let builder = match self.security {
+            SmtpSecurity::Starttls => SmtpTransport::starttls_relay(&self.smtp_host)?,
+            SmtpSecurity::Tls => SmtpTransport::relay(&self.smtp_host)?,
+            SmtpSecurity::None => SmtpTransport::builder_dangerous(&self.smtp_host),
+        };
+        let mut builder = builder.port(self.port()).timeout(Some(Duration::from_secs(30)));
+        if !self.username.is_empty() {
+            builder = builder.credentials(Credentials::new(self.username.clone(), self.password()?));
+        }
+        Ok(builder.build())
+    }
+}
+
+/// Describe one run for the digest, with its report if there is one.
+fn describe_run(summary: &RunSummary, out: &mut String) -> Option<(String, String)> {
+    let name = summary.md_file.display();
+    writeln!(out, "{} — {} (exit {})", name, summary.reason.describe(), summary.exit_code).ok();
+    writeln!(out, "  Tasks: {}/{} completed ({} this run), {} continues, {}",
+        summary.tasks_completed, summary.tasks_total, summary.tasks_completed_this_run,
+        summary.continues, format_elapsed(summary.duration_secs)).ok();
+    if let Some(error) = &summary.error {
+        writeln!(out, "  Error: {}", error).ok();
+    }
+    let run_id = summary.run_id.as_ref()?;
+    let dir = state::runs_dir().join(run_id);
+    let (Ok(run), Ok(events)) = (state::load_state(&dir), state::load_events(&dir)) else {
+        return None;
+    };
+    writeln!(out, "  Run: {}, started {}", run_id, run.started_at.format("%a %H:%M")).ok();
+
+    let list = |out: &mut String, title: &str, matches: &dyn Fn(&EventKind) -> Option<String>| {
+        let texts: Vec<String> = events.iter().filter_map(|e| matches(&e.kind)).collect();
+        if !texts.is_empty() {
+            writeln!(out, "  {}:", title).ok();
+            for text in texts {
+                writeln!(out, "    - {}", text).ok();
+            }
+        }
+    };
+    list(out, "Completed", &|kind| match kind {
+        EventKind::TaskCompleted { text } => Some(text.clone()),
+        _ => None,
+    });
+    list(out, "Failed", &|kind| match kind {
+        EventKind::TaskFailed { text } => Some(text.clone()),
+        _ => None,
+    });
+    let limit_wait = report::limit_wait_secs(&events);
+    if limit_wait > 0 {
+        writeln!(out, "  Waiting on usage limits: {}", format_elapsed(limit_wait)).ok();
+    }
+    if let Some(stat) = run.start_commit.as_deref().and_then(|commit| git::diff_stat(&run.working_dir, commit)) {
+        writeln!(out, "  Changes in {}:", run.working_dir.display()).ok();
+        for line in stat.lines() {
+            writeln!(out, "    {}", line.trim()).ok();
+        }
+    }
+    let report = fs::read_to_string(dir.join("report.md")).ok()?;
+    Some((format!("report-{}.md", run_id), report))
+}
+
+/// Mail a digest of `runs` to the configured recipients.
+pub fn send(email: &Email, runs: &[RunSummary]) -> Result<()> {
+    let completed: usize = runs.iter().map(|r| r.tasks_completed).sum();
+    let total: usize = runs.iter().map(|r| r.tasks_total).sum();
+    let failing = runs.iter().filter(|r| r.exit_code != 0).count();
+    let subject = match failing {
+        0 => format!("Claudia: all {} tasks done", total),
+        _ => format!("Claudia: {}/{} tasks done, {} of {} task file(s) unfinished", completed, total, failing, runs.len()),
+    };
+
+    let mut body = format!("Claudia digest, {}\n\n", Local::now().format("%a %Y-%m-%d %H:%M"));
+    let mut reports = Vec::new();
+    for run in runs {
+        reports.extend(describe_run(run, &mut body));
+        body.push('\n');
+    }
+
+    let mut message = Message::builder().from(email.from.parse()?).subject(subject);
+    for address in &email.to {
+        message = message.to(address.parse()?);
+    }
+    let mut parts = MultiPart::mixed().singlepart(SinglePart::plain(body));
+    if email.attach_report {
+        for (name, report) in reports {
+            let content_type = ContentType::parse("text/markdown; charset=utf-8")?;
+            parts = parts.singlepart(Attachment::new(name).body(report, content_type));
+        }
+    }
+    let message = message.multipart(parts)?;
+    email.transport()?.send(&message).with_context(|| format!("Failed to send the digest through {}", email.smtp_host))?;
+    Ok(())
+}
+
+/// Mail one digest per distinct `email` setting, with the runs whose task
+/// files use it. Failures are reported as warnings.
+pub fn send_all(runs: Vec<(Email, RunSummary)>) {
+    let mut digests: Vec<(Email, Vec<RunSummary>)> = Vec::new();
+    for (email, run) in runs {
+        match digests.iter_mut().find(|(e, _)| *e == email) {
+            Some((_, runs)) => runs.push(run),
+            None => digests.push((email, vec![run])),
+        }
+    }
+    for (email, runs) in digests {
+        match send(&email, &runs) {
+            Ok(()) => println!("Digest sent to {}", email.to.join(", ")),
+            Err(e) => eprintln!("[WARN] {:#}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::thread;
+
+    /// Accept one SMTP session and return the envelope and message it carried.
+    fn smtp_sink(listener: TcpListener) -> thread::JoinHandle<(Vec<String>, String)> {
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut envelope = Vec::new();
+            let mut data = String::new();
+            writer.write_all(b"220 sink ESMTP\r\n").unwrap();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 {
+                let command = line.trim_end().to_string();
+                line.clear();
+                let reply: &[u8] = match command.split(' ').next().unwrap_or("").to_uppercase().as_str() {
+                    "EHLO" | "HELO" => b"250 sink\r\n",
+                    "DATA" => {
+                        writer.write_all(b"354 go ahead\r\n").unwrap();
+                        while reader.read_line(&mut line).unwrap() > 0 && line != ".\r\n" {
+                            data.push_str(&line);
+                            line.clear();
+                        }
+                        line.clear();
+                        b"250 queued\r\n"
+                    }
+                    "QUIT" => {
+                        writer.write_all(b"221 bye\r\n").unwrap();
+                        break;
+                    }
+                    _ => {
+                        envelope.push(command);
+                        b"250 ok\r\n"
+                    }
+                };
+                writer.write_all(reply).unwrap();
+            }
+            (envelope, data)
+        })
+    }
+
+    #[test]
+    fn sends_digest_to_local_sink() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let sink = smtp_sink(listener);
+        let email = Email {
+            smtp_host: "127.0.0.1".to_string(),
+            smtp_port: port,
+            security: SmtpSecurity::None,
+            from: "claudia@example.com".to_string(),
+            to: vec!["me@example.com".to_string()],
+            ..Email::default()
+        };
+        let run = RunSummary::from_error(Path::new("tasks.md"), &anyhow::anyhow!("agent not found"));
+        send(&email, &[run]).unwrap();
+
+        let (envelope, data) = sink.join().unwrap();
+        assert!(envelope.iter().any(|c| c.starts_with("MAIL FROM:<claudia@example.com>")));
+        assert!(envelope.iter().any(|c| c.starts_with("RCPT TO:<me@example.com>")));
+        assert!(data.contains("Subject: Claudia: 0/0 tasks done, 1 of 1 task file(s) unfinished"));
+        assert!(data.contains("tasks.md"));
+        assert!(data.contains("Error: agent not found"));
+    }
+
+    #[test]
+    fn validate_requires_addresses() {
+        let email = Email { smtp_host: "mail".to_string(), ..Email::default() };
+        assert!(email.validate().is_err());
+        let email = Email { to: vec!["not an address".to_string()], from: "a@b.c".to_string(), ..email };
+        assert!(email.validate().is_err());
+    }
+}
@@ -0,0 +1,33 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::process::Command;
+
+/// Output of a successful git command run in `dir`.
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).current_dir(dir).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The commit checked out in `dir`, if it is a git repository.
+pub fn head_commit(dir: &Path) -> Option<String> {
+    git(dir, &["rev-parse", "HEAD"]).map(|commit| commit.trim().to_string())
+}
+
+/// `git diff --stat` of everything changed in `dir` since `commit`, committed or not.
+pub fn diff_stat(dir: &Path, commit: &str) -> Option<String> {
+    let stat = git(dir, &["diff", "--stat", commit])?.trim_end().to_string();
+    (!stat.is_empty()).then_some(stat)
+}
+
+/// A value that changes whenever work is committed or files change in the
+/// repository at `dir`, ignoring the file `exclude` (the task file).
+pub fn tree_fingerprint(dir: &Path, exclude: &Path) -> Option<u64> {
+    let exclude = exclude.canonicalize().ok()?;
+    let pathspec = format!(":(exclude){}", exclude.display());
+    let mut hasher = DefaultHasher::new();
+    git(dir, &["rev-parse", "HEAD"])?.hash(&mut hasher);
+    git(dir, &["status", "--porcelain", "--untracked-files=all", "--", ".", &pathspec])?.hash(&mut hasher);
+    git(dir, &["diff", "HEAD", "--", ".", &pathspec])?.hash(&mut hasher);
+    Some(hasher.finish())
+}
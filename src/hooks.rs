@@ -0,0 +1,145 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::settings::format_duration;
+use crate::ExitReason;
+
+/// Shell commands run at points of a run. Empty commands are skipped.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    /// Before the task file is read and Claude is started
+    pub pre_run: String,
+    /// After the run ended, before the summary and report are written
+    pub post_run: String,
+    /// When Claude moves on to a new task
+    pub on_task_start: String,
+    /// When a task is checked off
+    pub on_task_complete: String,
+    /// When Claude hits its usage limit
+    pub on_limit: String,
+    /// When the run ends because Claude is stuck in a loop
+    pub on_stuck: String,
+    /// Last, also when the run failed to start
+    pub on_exit: String,
+}
+
+/// A point of the run at which a hook can run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    PreRun,
+    PostRun,
+    TaskStart,
+    TaskComplete,
+    Limit,
+    Stuck,
+    Exit,
+}
+
+impl Hook {
+    /// Name as written in the config.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::PreRun => "pre_run",
+            Hook::PostRun => "post_run",
+            Hook::TaskStart => "on_task_start",
+            Hook::TaskComplete => "on_task_complete",
+            Hook::Limit => "on_limit",
+            Hook::Stuck => "on_stuck",
+            Hook::Exit => "on_exit",
+        }
+    }
+
+    /// The configured command for this hook.
+    pub fn command(self, hooks: &Hooks) -> &str {
+        match self {
+            Hook::PreRun => &hooks.pre_run,
+            Hook::PostRun => &hooks.post_run,
+            Hook::TaskStart => &hooks.on_task_start,
+            Hook::TaskComplete => &hooks.on_task_complete,
+            Hook::Limit => &hooks.on_limit,
+            Hook::Stuck => &hooks.on_stuck,
+            Hook::Exit => &hooks.on_exit,
+        }
+    }
+}
+
+/// What a hook is told about the run, as JSON in the file named by
+/// `CLAUDIA_CONTEXT` and as `CLAUDIA_<FIELD>` environment variables.
+#[derive(Debug, Clone, Serialize)]
+pub struct HookContext {
+    pub hook: &'static str,
+    pub run_id: String,
+    pub task_file: String,
+    pub working_dir: String,
+    /// The task started, completed or being worked on
+    pub task: Option<String>,
+    /// One-based position of `task` in the task file
+    pub task_id: Option<usize>,
+    pub continues: u32,
+    pub tasks_total: usize,
+    pub tasks_completed: usize,
+    /// Only for `post_run` and `on_exit`
+    pub exit_reason: Option<ExitReason>,
+    pub exit_code: Option<i32>,
+}
+
+/// Run `command` with `sh -c` in `working_dir`, writing the context to
+/// `context_file` first. Its output is appended to `log_file`. Returns an
+/// error if it fails or takes longer than `timeout`, in which case it is killed.
+pub fn run(
+    command: &str,
+    context: &HookContext,
+    working_dir: &Path,
+    context_file: &Path,
+    log_file: &Path,
+    timeout: Duration,
+) -> Result<()> {
+    let json = serde_json::to_value(context)?;
+    fs::write(context_file, serde_json::to_string_pretty(&json)?)
+        .with_context(|| format!("Failed to write {}", context_file.display()))?;
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)
+        .with_context(|| format!("Failed to open {}", log_file.display()))?;
+
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c")
+        .arg(command)
+        .current_dir(working_dir)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        .env("CLAUDIA_CONTEXT", context_file);
+    for (key, value) in json.as_object().into_iter().flatten() {
+        let value = match value {
+            serde_json::Value::Null => continue,
+            serde_json::Value::String(text) => text.clone(),
+            other => other.to_string(),
+        };
+        cmd.env(format!("CLAUDIA_{}", key.to_uppercase()), value);
+    }
+
+    let mut child = cmd.spawn().with_context(|| format!("Failed to run '{}'", command))?;
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if !status.success() {
+                anyhow::bail!("'{}' failed ({})", command, status);
+            }
+            return Ok(());
+        }
+        if started.elapsed() > timeout {
+            child.kill().ok();
+            child.wait().ok();
+            anyhow::bail!("'{}' did not finish within {} and was stopped", command, format_duration(timeout));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,287 @@
+use anyhow::Result;
+use regex::Regex;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::ConfigError;
+
+/// Order in which several task files are processed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum FileOrder {
+    /// As given on the command line; globs and directories in name order
+    #[default]
+    Given,
+    /// By path
+    Name,
+    /// Least recently modified first
+    Modified,
+}
+
+fn is_glob(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// Regex for a glob: `*` and `?` stay within one path component, `**`
+/// matches any number of directories, `[a-z]` and `[!a-z]` match one
+/// character of or not of a set. A `[` without a closing `]` is literal.
+fn glob_regex(pattern: &str) -> Result<Regex> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut re = String::from("^");
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                i += 1;
+                // `**/` also matches no directory at all
+                if chars.get(i + 1) == Some(&'/') {
+                    i += 1;
+                    re.push_str("(?:.*/)?");
+                } else {
+                    re.push_str(".*");
+                }
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            '[' => match class_regex(&chars[i + 1..]) {
+                Some((class, len)) => {
+                    re.push_str(&class);
+                    i += len;
+                }
+                None => re.push_str(r"\["),
+            },
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    re.push('$');
+    Regex::new(&re).map_err(|e| ConfigError(format!("Invalid glob '{}': {}", pattern, e)).into())
+}
+
+/// Regex for the character class at the start of `chars`, just after its
+/// `[`, and the number of characters it takes up to its `]`. A `]` right
+/// after the `[` (or `[!`) is a member; `-` between two members is a range.
+fn class_regex(chars: &[char]) -> Option<(String, usize)> {
+    let mut re = String::from("[");
+    let mut i = 0;
+    if chars.first() == Some(&'!') {
+        re.push('^');
+        i += 1;
+    }
+    let first = i;
+    loop {
+        let c = *chars.get(i)?;
+        if c == ']' && i > first {
+            re.push(']');
+            return Some((re, i + 1));
+        }
+        re.push_str(&class_member(c));
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|end| *end != ']') {
+            re.push('-');
+            re.push_str(&class_member(chars[i + 2]));
+            i += 2;
+        }
+        i += 1;
+    }
+}
+
+/// One character inside a regex character class, escaped if it has a
+/// meaning there (including the `&&`, `--` and `~~` set operators).
+fn class_member(c: char) -> String {
+    if matches!(c, '\\' | ']' | '[' | '^' | '-' | '&' | '~') {
+        format!("\\{}", c)
+    } else {
+        c.to_string()
+    }
+}
+
+/// Files under `dir` down to `depth` levels (unlimited if `None`), skipping hidden directories.
+fn walk(dir: &Path, depth: Option<usize>, out: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            if !hidden && depth != Some(1) {
+                walk(&path, depth.map(|d| d - 1), out);
+            }
+        } else {
+            out.push(path);
+        }
+    }
+}
+
+fn expand_glob(pattern: &str) -> Result<Vec<PathBuf>> {
+    // Walk from the longest prefix without wildcards
+    let path = Path::new(pattern);
+    let mut base = PathBuf::new();
+    let mut rest = Vec::new();
+    for component in path.components() {
+        let text = component.as_os_str().to_string_lossy();
+        if rest.is_empty() && !is_glob(&text) {
+            base.push(component);
+        } else {
+            rest.push(text.into_owned());
+        }
+    }
+    let depth = if rest.iter().any(|c| c.contains("**")) { None } else { Some(rest.len()) };
+    let walk_dir = if base.as_os_str().is_empty() { PathBuf::from(".") } else { base.clone() };
+
+    let regex = glob_regex(&rest.join("/"))?;
+    let mut files = Vec::new();
+    walk(&walk_dir, depth, &mut files);
+    let mut matches: Vec<PathBuf> = files
+        .into_iter()
+        .filter(|file| {
+            file.strip_prefix(&walk_dir)
+                .is_ok_and(|relative| regex.is_match(&relative.to_string_lossy()))
+        })
+        // Keep paths relative to the current directory as the user wrote them
+        .map(|file| file.strip_prefix(".").map(Path::to_path_buf).unwrap_or(file))
+        .collect();
+    matches.sort();
+    Ok(matches)
+}
+
+/// The `*.md` files directly inside `dir`, in name order.
+fn markdown_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|e| ConfigError(format!("Failed to read directory '{}': {}", dir.display(), e)))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "md"))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Turn the task file arguments (files, globs and directories) into the list
+/// of task files to process, without duplicates.
+pub fn expand(inputs: &[PathBuf], order: FileOrder) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        let text = input.to_string_lossy();
+        let expanded = if is_glob(&text) && !input.exists() {
+            expand_glob(&text)?
+        } else if input.is_dir() {
+            markdown_files(input)?
+        } else {
+            // A missing file is reported when its run starts, like for a single file
+            vec![input.clone()]
+        };
+        if expanded.is_empty() {
+            return Err(ConfigError(format!("No task files found for '{}'", input.display())).into());
+        }
+        files.extend(expanded);
+    }
+
+    let mut seen = HashSet::new();
+    files.retain(|file| seen.insert(file.canonicalize().unwrap_or_else(|_| file.clone())));
+
+    match order {
+        FileOrder::Given => {}
+        FileOrder::Name => files.sort(),
+        FileOrder::Modified => {
+            files.sort_by_key(|file| fs::metadata(file).and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH))
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        glob_regex(pattern).unwrap().is_match(path)
+    }
+
+    /// A fresh directory with `files` (relative paths) created empty.
+    fn tree(name: &str, files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("claudia-inputs-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        for file in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "- [ ] Task\n").unwrap();
+        }
+        dir
+    }
+
+    fn names(dir: &Path, files: Vec<PathBuf>) -> Vec<String> {
+        files.iter().map(|f| f.strip_prefix(dir).unwrap().to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn star_and_question_mark_stay_in_one_component() {
+        assert!(matches("*.md", "plan.md"));
+        assert!(!matches("*.md", "sub/plan.md"));
+        assert!(matches("plan-?.md", "plan-1.md"));
+        assert!(!matches("plan-?.md", "plan-10.md"));
+        assert!(!matches("a?b.md", "a/b.md"));
+    }
+
+    #[test]
+    fn double_star_matches_any_number_of_directories() {
+        assert!(matches("**/*.md", "plan.md"));
+        assert!(matches("**/*.md", "a/b/plan.md"));
+        assert!(matches("docs/**/plan.md", "docs/plan.md"));
+        assert!(matches("docs/**", "docs/a/b.md"));
+        assert!(!matches("docs/**/plan.md", "other/plan.md"));
+    }
+
+    #[test]
+    fn brackets_match_ranges_and_negated_sets() {
+        assert!(matches("[a-b].md", "a.md"));
+        assert!(matches("[a-b].md", "b.md"));
+        assert!(!matches("[a-b].md", "-.md"));
+        assert!(!matches("[a-b].md", "c.md"));
+        assert!(matches("[!x].md", "y.md"));
+        assert!(!matches("[!x].md", "x.md"));
+        assert!(matches("[a-].md", "-.md"));
+        assert!(matches("[]a].md", "].md"));
+        assert!(matches("[!]].md", "a.md"));
+        assert!(!matches("[!]].md", "].md"));
+        assert!(matches("[a&&b].md", "&.md"));
+        assert!(matches("[x^].md", "^.md"));
+    }
+
+    #[test]
+    fn other_characters_are_literal() {
+        assert!(matches("v1.0+(draft)*.md", "v1.0+(draft) 2.md"));
+        assert!(!matches("v1.0.md", "v1x0.md"));
+        assert!(matches("notes[.md", "notes[.md"));
+        assert!(matches("$HOME^{x}|y.md", "$HOME^{x}|y.md"));
+    }
+
+    #[test]
+    fn expands_globs_in_name_order() {
+        let dir = tree("glob", &["a.md", "b.md", "-.md", "c.txt", "sub/c.md", "sub/deep/d.md", ".hidden/e.md"]);
+        let files = expand(&[dir.join("[a-b].md")], FileOrder::Given).unwrap();
+        assert_eq!(names(&dir, files), ["a.md", "b.md"]);
+        let files = expand(&[dir.join("**/*.md")], FileOrder::Given).unwrap();
+        assert_eq!(names(&dir, files), ["-.md", "a.md", "b.md", "sub/c.md", "sub/deep/d.md"]);
+        let files = expand(&[dir.join("*/*.md")], FileOrder::Given).unwrap();
+        assert_eq!(names(&dir, files), ["sub/c.md"]);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn expands_directories_to_their_markdown_files() {
+        let dir = tree("dir", &["b.md", "a.md", "notes.txt", "sub/c.md"]);
+        let files = expand(&[dir.clone(), dir.join("a.md")], FileOrder::Given).unwrap();
+        assert_eq!(names(&dir, files), ["a.md", "b.md"]);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn reports_globs_without_matches() {
+        let dir = tree("none", &["a.md"]);
+        let error = expand(&[dir.join("*.txt")], FileOrder::Given).unwrap_err();
+        assert!(error.to_string().starts_with("No task files found for"), "{}", error);
+        assert!(error.downcast_ref::<ConfigError>().is_some());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
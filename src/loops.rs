@@ -0,0 +1,122 @@
+use regex::Regex;
+
+/// Longest cycle of responses the detector looks for: 1 is the same response
+/// over and over, 2 is A, B, A, B, and so on.
+pub const MAX_PERIOD: usize = 3;
+
+/// Responses shorter than this after normalizing count as empty.
+const MIN_CHARS: usize = 10;
+
+/// The end of `output`, reduced to what stays the same when Claude repeats
+/// itself: no escape sequences, spinner glyphs or box drawing (the frame of
+/// Claude's input box would make any two screens look alike), digits (counters, times,
+/// timestamps) replaced by `#`, and runs of whitespace collapsed. At most the
+/// last `max_chars` characters are kept; short responses become empty.
+pub fn normalize(output: &str, max_chars: usize) -> String {
+    let escape = Regex::new(r"\x1b\[[0-9;?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\)|\x1b[@-_]").unwrap();
+    let numbers = Regex::new(r"\d+(?:[.:,/-]\d+)*").unwrap();
+    let text = escape.replace_all(output, " ");
+    let text: String = text.chars().filter(|c| c.is_whitespace() || !(is_decoration(*c) || c.is_control())).collect();
+    let text = numbers.replace_all(&text, "#");
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let skip = text.chars().count().saturating_sub(max_chars);
+    let text: String = text.chars().skip(skip).collect();
+    if text.chars().count() < MIN_CHARS {
+        String::new()
+    } else {
+        text
+    }
+}
+
+/// Glyphs of the animated spinners in Claude's status line, and box drawing.
+fn is_decoration(c: char) -> bool {
+    matches!(c, '✢' | '✳' | '✶' | '✻' | '✽' | '·' | '∗' | '⏺' | '●' | '◐' | '◓' | '◑' | '◒')
+        || ('\u{2500}'..='\u{257F}').contains(&c) // Box drawing
+        || ('\u{2800}'..='\u{28FF}').contains(&c) // Braille spinners
+}
+
+/// How alike `a` and `b` are, from 0 (nothing in common) to 1 (identical),
+/// by edit distance relative to the longer one.
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    // Levenshtein distance, one row at a time
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    1.0 - row[b.len()] as f64 / longest as f64
+}
+
+/// The length of the cycle the latest responses in `history` (oldest first)
+/// are stuck in, if any. A cycle of period `p` needs the last
+/// `max(window, 2p)` responses to each be at least `threshold` similar to
+/// the one `p` before it.
+pub fn find_cycle(history: &[String], window: usize, threshold: f64) -> Option<usize> {
+    (1..=MAX_PERIOD).find(|&period| {
+        let needed = window.max(2 * period);
+        if history.len() < needed {
+            return false;
+        }
+        let recent = &history[history.len() - needed..];
+        (period..needed).all(|i| similarity(&recent[i], &recent[i - period]) >= threshold)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(responses: &[&str]) -> Vec<String> {
+        responses.iter().map(|r| r.to_string()).collect()
+    }
+
+    #[test]
+    fn normalizes_away_what_changes_between_repeats() {
+        let output = "\x1b[1m⏺ Build failed\x1b[0m after 12.5s at 10:42\n\n  ╭────╮ 3 errors";
+        assert_eq!(normalize(output, 100), "Build failed after #s at # # errors");
+        assert_eq!(normalize(output, 13), "at # # errors");
+        assert_eq!(normalize("✻ ok 42", 100), "");
+    }
+
+    #[test]
+    fn similarity_is_relative_edit_distance() {
+        assert_eq!(similarity("", ""), 1.0);
+        assert_eq!(similarity("same text", "same text"), 1.0);
+        assert_eq!(similarity("abc", ""), 0.0);
+        assert!((similarity("kitten", "sitting") - (1.0 - 3.0 / 7.0)).abs() < 1e-9);
+        // One character off in twenty is just above a 0.9 threshold, three are below
+        assert!(similarity("Running the tests #1", "Running the tests #2") >= 0.9);
+        assert!(similarity("Running the tests #1", "Running the bests #234") < 0.9);
+    }
+
+    #[test]
+    fn finds_the_same_response_over_and_over() {
+        let responses = history(&["I will fix the build now", "I will fix the build now", "I will fix the build now!"]);
+        assert_eq!(find_cycle(&responses, 3, 0.9), Some(1));
+        assert_eq!(find_cycle(&responses, 4, 0.9), None);
+        assert_eq!(find_cycle(&responses, 3, 1.0), None);
+    }
+
+    #[test]
+    fn finds_alternating_responses() {
+        let a = "Running cargo test to check";
+        let b = "The tests fail, let me look at the error";
+        let responses = history(&["Starting on the parser", a, b, a, b]);
+        assert_eq!(find_cycle(&responses, 3, 0.9), Some(2));
+        assert_eq!(find_cycle(&responses[..4], 3, 0.9), None);
+        let c = "Reverting the change to the lexer";
+        assert_eq!(find_cycle(&history(&[a, b, c, a, b, c]), 3, 0.9), Some(3));
+        assert_eq!(find_cycle(&history(&[a, b, c, a, c, b]), 3, 0.9), None);
+    }
+}
@@ -1,690 +1,707 @@
-use anyhow::{Result, Context};
-use clap::Parser as ClapParser;
-use regex::Regex;
-use std::io::{Read, Write, IsTerminal};
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
-use std::sync::mpsc;
-use std::thread;
-use std::time::{Duration, Instant};
+use anyhow::Result;
 use chrono::{DateTime, Local, NaiveTime};
-use portable_pty::{native_pty_system, CommandBuilder, PtySize};
-use std::fs;
-use crossterm::{
-    terminal::{enable_raw_mode, disable_raw_mode},
-    event::{self, Event, KeyCode, KeyModifiers},
-};
+use clap::{Parser as ClapParser, Subcommand};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crossterm::terminal::disable_raw_mode;
+use serde::{Deserialize, Serialize};
+
+mod activity;
+mod attach;
+mod claudia;
+mod commands;
+mod config;
+mod control;
+mod daemon;
+mod digest;
+mod git;
+mod hooks;
+mod inputs;
+mod loops;
+mod notify;
+mod plan;
+mod progress;
+mod report;
+mod schedule;
+mod settings;
+mod state;
+mod tasks;
+mod template;
+
+use claudia::Claudia;
+use config::ConfigSources;
+use inputs::FileOrder;
+use settings::Layer;
+use state::RunRecord;
+
+/// Set from `--debug`.
+static DEBUG: AtomicBool = AtomicBool::new(false);
+
+/// Whether the run was started with `--debug`, to print what claudia sees and does.
+pub fn debug_enabled() -> bool {
+    DEBUG.load(Ordering::Relaxed)
+}
 
 #[derive(ClapParser, Debug)]
 #[command(author, version, about = "Automate Claude task execution from Markdown files", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// Path to the Markdown file containing tasks
-    md_file: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Running `claudia <MD_FILE>` without a subcommand is the same as `claudia run <MD_FILE>`
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run Claude on a task file until all tasks are done
+    Run(RunArgs),
+    /// Parse and lint a task file without launching Claude
+    #[command(alias = "check")]
+    Validate {
+        /// Path to the Markdown file containing tasks
+        md_file: PathBuf,
+
+        #[command(flatten)]
+        settings: SettingsArgs,
+    },
+    /// Show the tasks in a file with their states and IDs
+    List {
+        /// Path to the Markdown file containing tasks
+        md_file: PathBuf,
+    },
+    /// Create a task file and a claudia.toml to start from
+    Init {
+        /// Directory to create the files in
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+
+        /// Name of the task file to create
+        #[arg(long, default_value = "tasks.md")]
+        file: String,
+
+        /// Overwrite existing files
+        #[arg(long)]
+        force: bool,
+    },
+    /// Show the state of a running or finished run
+    Status {
+        /// Run ID or a unique prefix of one [default: most recent run]
+        run: Option<String>,
+
+        /// List all recorded runs
+        #[arg(long, conflicts_with = "run")]
+        all: bool,
+
+        /// Print the raw state as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Render a Markdown report of a run from its event log
+    Report {
+        /// Run ID or a unique prefix of one [default: most recent run]
+        run: Option<String>,
+
+        /// Write the report to a file instead of stdout
+        #[arg(long, short, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+    /// Add task files to the daemon queue
+    Submit {
+        /// Task files to queue, processed in the given order
+        #[arg(required = true)]
+        md_files: Vec<PathBuf>,
+
+        /// Profile to run the jobs with
+        #[arg(long, short, value_name = "NAME")]
+        profile: Option<String>,
+
+        /// Config file to run the jobs with
+        #[arg(long, value_name = "FILE")]
+        config: Option<PathBuf>,
+
+        /// Queue directory [default: <state dir>/queue]
+        #[arg(long, value_name = "DIR")]
+        queue_dir: Option<PathBuf>,
+    },
+    /// Process queued task files one after another
+    Daemon {
+        /// Queue directory [default: <state dir>/queue]
+        #[arg(long, value_name = "DIR")]
+        queue_dir: Option<PathBuf>,
+
+        /// Exit once the queue is empty instead of waiting for new jobs
+        #[arg(long)]
+        once: bool,
+
+        /// How often to look for new jobs
+        #[arg(long, value_name = "DURATION", default_value = "5s", value_parser = parse_duration_arg)]
+        poll_interval: Duration,
+    },
+    /// Show queued, running and finished daemon jobs
+    Queue {
+        /// Queue directory [default: <state dir>/queue]
+        #[arg(long, value_name = "DIR")]
+        queue_dir: Option<PathBuf>,
+
+        /// Print the queue as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Inspect configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Mirror the screen of a run and type into it; Ctrl+] detaches
+    Attach {
+        /// Run ID or a unique prefix of one [default: most recent run]
+        run: Option<String>,
+
+        /// Only watch; don't forward keystrokes
+        #[arg(long)]
+        read_only: bool,
+    },
+    /// Control a run in progress through its control socket
+    Ctl {
+        /// Run ID or a unique prefix of one [default: most recent run]
+        #[arg(long, short)]
+        run: Option<String>,
+
+        #[command(subcommand)]
+        action: CtlAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CtlAction {
+    /// Show the live state of the run
+    Status,
+    /// Stop sending Continues until resumed
+    Pause,
+    /// Send Continues again after a pause
+    Resume,
+    /// Type a message into Claude's prompt
+    Inject {
+        /// Message to send
+        message: String,
+    },
+    /// Treat a task as done and tell Claude to move on
+    SkipTask {
+        /// Task ID as shown by `claudia list`
+        id: usize,
+    },
+    /// Stop the run once the current task is checked off
+    StopAfterCurrent,
+    /// Stop Claude and end the run now
+    Abort,
+}
+
+#[derive(clap::Args, Debug)]
+struct RunArgs {
+    /// Markdown task files, globs (e.g. "backlog/*.md") or directories of *.md
+    /// files, processed one after another
+    #[arg(required = true, value_name = "MD_FILE")]
+    md_files: Vec<PathBuf>,
+
+    /// Order in which several task files are processed
+    #[arg(long, value_enum, default_value_t = FileOrder::Given)]
+    order: FileOrder,
     
     /// Enable debug mode to see raw output
     #[arg(long, short)]
     debug: bool,
+
+    /// Print a JSON summary of the run on stdout when it ends
+    #[arg(long)]
+    json_summary: bool,
+
+    /// Show what the run would do (checkbox changes, prompt, configuration)
+    /// without starting Claude or writing the task file
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Start the run in the background, independent of this terminal;
+    /// use `claudia attach` to watch it
+    #[arg(long, conflicts_with = "dry_run")]
+    detach: bool,
+
+    /// Stop the run at this time of day (e.g. 07:30), after asking Claude to wrap up
+    #[arg(long, value_name = "HH:MM", value_parser = parse_time_arg)]
+    until: Option<NaiveTime>,
+
+    /// Stop the run after this long (e.g. 6h), after asking Claude to wrap up
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration_arg)]
+    max_duration: Option<Duration>,
+
+    #[command(flatten)]
+    settings: SettingsArgs,
 }
 
-struct Claudia {
-    md_file: PathBuf,
-    output_buffer: Arc<Mutex<String>>,
-    last_output_time: Arc<Mutex<Instant>>,
-    continue_count: Arc<Mutex<u32>>,
-    status: Arc<Mutex<String>>,
-    response_history: Arc<Mutex<Vec<String>>>,
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Print the effective configuration and where each value comes from
+    Show {
+        /// Task file whose directory starts the config file search [default: current directory]
+        md_file: Option<PathBuf>,
+
+        #[command(flatten)]
+        settings: SettingsArgs,
+    },
+}
+
+/// Options that select and override run settings.
+#[derive(clap::Args, Debug, Default)]
+struct SettingsArgs {
+    /// Config file to load on top of the discovered claudia.toml files
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Profile from the config files to apply
+    #[arg(long, short, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// How long Claude must be idle before sending Continue (e.g. 90s, 10m) [default: 60s]
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration_arg)]
+    idle_timeout: Option<Duration>,
+
+    /// Maximum number of Continue commands per run [default: 50]
+    #[arg(long, value_name = "N")]
+    max_continues: Option<u32>,
+
+    /// Number of consecutive responses compared by the loop detector [default: 3]
+    #[arg(long, value_name = "N")]
+    loop_window: Option<usize>,
+
+    /// Trailing characters of each response compared by the loop detector [default: 500]
+    #[arg(long, value_name = "N")]
+    loop_compare_chars: Option<usize>,
+
+    /// How similar responses must be (0-1) for the loop detector to call them a repeat [default: 0.9]
+    #[arg(long, value_name = "RATIO")]
+    loop_similarity: Option<f64>,
+
+    /// Characters of recent output kept for pattern detection [default: 2000]
+    #[arg(long, value_name = "N")]
+    output_buffer_chars: Option<usize>,
+
+    /// Only work inside this window, e.g. "Mon-Fri 22:00-07:00"; repeat for several
+    #[arg(long = "window", value_name = "SPEC")]
+    run_windows: Vec<String>,
 }
 
-impl Claudia {
-    fn new(md_file: PathBuf) -> Self {
-        Self {
-            md_file,
-            output_buffer: Arc::new(Mutex::new(String::new())),
-            last_output_time: Arc::new(Mutex::new(Instant::now())),
-            continue_count: Arc::new(Mutex::new(0)),
-            status: Arc::new(Mutex::new("Starting...".to_string())),
-            response_history: Arc::new(Mutex::new(Vec::new())),
+impl SettingsArgs {
+    /// Where to load settings from for `md_file`, or for the current
+    /// directory without one.
+    fn sources(&self, md_file: Option<&Path>) -> ConfigSources {
+        let mut cli = Layer::new("command line");
+        if let Some(v) = self.idle_timeout {
+            cli.values.insert("idle_timeout".into(), settings::format_duration(v).into());
         }
-    }
-    
-    // Helper function to safely get last N chars from a string
-    fn safe_suffix(s: &str, max_chars: usize) -> &str {
-        let char_count = s.chars().count();
-        if char_count <= max_chars {
-            return s;
+        if let Some(v) = self.max_continues {
+            cli.values.insert("max_continues".into(), i64::from(v).into());
         }
-        
-        let skip_chars = char_count - max_chars;
-        let mut char_indices = s.char_indices();
-        
-        // Skip to the desired starting position
-        for _ in 0..skip_chars {
-            char_indices.next();
+        if let Some(v) = self.loop_window {
+            cli.values.insert("loop_window".into(), (v as i64).into());
         }
-        
-        // Get the byte index of the start position
-        if let Some((byte_idx, _)) = char_indices.next() {
-            &s[byte_idx..]
-        } else {
-            s
+        if let Some(v) = self.loop_compare_chars {
+            cli.values.insert("loop_compare_chars".into(), (v as i64).into());
+        }
+        if let Some(v) = self.loop_similarity {
+            cli.values.insert("loop_similarity".into(), v.into());
+        }
+        if let Some(v) = self.output_buffer_chars {
+            cli.values.insert("output_buffer_chars".into(), (v as i64).into());
+        }
+        if !self.run_windows.is_empty() {
+            cli.values.insert("run_windows".into(), self.run_windows.clone().into());
         }
-    }
 
-    fn create_initial_prompt(&self) -> String {
-        format!(
-            "Please read and complete all tasks in the file: {}\n\
-             The file is located at: {}\n\
-             Work through each task and:\n\
-             1. Complete the task as described\n\
-             2. Edit the markdown file to change [ ] to [x] for each completed task",
-            self.md_file.file_name().unwrap_or_default().to_string_lossy(),
-            self.md_file.display()
-        )
+        ConfigSources {
+            start_dir: md_file.map(parent_dir).unwrap_or(Path::new(".")).to_path_buf(),
+            task_file: md_file.map(Path::to_path_buf),
+            explicit: self.config.clone(),
+            profile: self.profile.clone(),
+            cli: Some(cli).filter(|l| !l.is_empty()),
+        }
     }
+}
 
-    fn update_status(&self, status: &str) {
-        *self.status.lock().unwrap() = status.to_string();
-        self.display_status();
+fn parse_time_arg(s: &str) -> Result<NaiveTime> {
+    schedule::parse_time_of_day(s)
+}
+
+fn parse_duration_arg(s: &str) -> Result<Duration> {
+    settings::parse_duration(s)
+}
+
+/// Directory containing `path`, or the current directory for bare file names.
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
     }
+}
 
-    fn display_status(&self) {
-        let status = self.status.lock().unwrap();
-        let continues = self.continue_count.lock().unwrap();
-        println!("\n╔════════════════════ CLAUDIA STATUS ════════════════════╗");
-        println!("║ {:<54} ║", status);
-        if *continues > 0 {
-            println!("║ Continues sent: {:<38} ║", continues);
+/// Why a run ended. Each reason maps to its own process exit code so that
+/// wrappers (cron, CI) can branch on the result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ExitReason {
+    AllDone,
+    PartialProgress,
+    StuckLoop,
+    ContinueBudgetExhausted,
+    AgentCrashed,
+    Stopped,
+    DeadlineReached,
+    Interrupted,
+    ConfigError,
+}
+
+impl ExitReason {
+    fn code(self) -> i32 {
+        match self {
+            ExitReason::AllDone => 0,
+            ExitReason::PartialProgress => 10,
+            ExitReason::StuckLoop => 11,
+            ExitReason::ContinueBudgetExhausted => 12,
+            ExitReason::AgentCrashed => 13,
+            ExitReason::Stopped => 14,
+            ExitReason::DeadlineReached => 15,
+            ExitReason::ConfigError => 78, // EX_CONFIG from sysexits.h
+            ExitReason::Interrupted => 130, // 128 + SIGINT, as shells report it
         }
-        println!("╚════════════════════════════════════════════════════════╝\n");
     }
 
-    fn run(&self) -> Result<()> {
-        // Check if claude command exists
-        if std::process::Command::new("which")
-            .arg("claude")
-            .output()
-            .map(|output| !output.status.success())
-            .unwrap_or(true) {
-            anyhow::bail!("Claude command not found. Please ensure Claude CLI is installed and in PATH.");
-        }
-        
-        // Ensure all tasks have checkboxes
-        self.ensure_checkboxes()?;
-        
-        let initial_prompt = self.create_initial_prompt();
-        
-        // Get the directory of the markdown file
-        let working_dir = self.md_file.parent()
-            .unwrap_or_else(|| std::path::Path::new("."));
-        
-        println!("Starting Claude with task file: {}", self.md_file.display());
-        println!("Working directory: {}", working_dir.display());
-        println!();
-        
-        // Create a new pty
-        let pty_system = native_pty_system();
-        
-        // Create a new pty pair with terminal size
-        let pair = pty_system.openpty(PtySize {
-            rows: 40,
-            cols: 120,
-            pixel_width: 0,
-            pixel_height: 0,
-        }).context("Failed to create PTY")?;
-        
-        // Build the command
-        let mut cmd = CommandBuilder::new("claude");
-        cmd.arg("--dangerously-skip-permissions");
-        cmd.cwd(working_dir);
-        
-        // Spawn the command in the pty
-        let mut child = pair.slave.spawn_command(cmd)
-            .context("Failed to spawn Claude process")?;
-        
-        // Get reader/writer for the master side
-        let mut reader = pair.master.try_clone_reader()
-            .context("Failed to clone reader")?;
-        let mut writer = pair.master.take_writer()
-            .context("Failed to get writer")?;
-        
-        // Send initial prompt
-        self.update_status("Sending initial prompt to Claude...");
-        if std::env::args().any(|arg| arg == "--debug" || arg == "-d") {
-            eprintln!("[DEBUG] Sending initial prompt: {:?}", initial_prompt);
-        }
-        // Write the text first
-        write!(writer, "{}", initial_prompt)?;
-        writer.flush()?;
-        thread::sleep(Duration::from_millis(50));
-        // Then send Enter key (carriage return)
-        writer.write_all(&[0x0D])?; // CR (Enter key)
-        writer.flush()?;
-        thread::sleep(Duration::from_millis(100)); // Give PTY time to process
-        self.update_status("Claude is working...");
-        
-        // Clone Arc references for the monitoring thread
-        let output_buffer_clone = Arc::clone(&self.output_buffer);
-        let last_output_time_clone = Arc::clone(&self.last_output_time);
-        
-        // Create channel for user input (now sends raw bytes)
-        let (user_tx, user_rx) = mpsc::channel::<Vec<u8>>();
-        
-        // Setup Ctrl+C handler before enabling raw mode
-        let should_exit = Arc::new(Mutex::new(false));
-        let should_exit_clone = Arc::clone(&should_exit);
-        
-        // Only enable raw mode and start input thread if we're in a TTY
-        let is_tty = std::io::stdin().is_terminal();
-        
-        if is_tty {
-            // Enable raw mode for terminal
-            enable_raw_mode().context("Failed to enable raw mode")?;
+    fn describe(self) -> &'static str {
+        match self {
+            ExitReason::AllDone => "All tasks completed",
+            ExitReason::PartialProgress => "Claude exited with tasks remaining",
+            ExitReason::StuckLoop => "Claude appeared to be stuck in a loop",
+            ExitReason::ContinueBudgetExhausted => "Maximum continue limit reached",
+            ExitReason::AgentCrashed => "Claude process exited with an error",
+            ExitReason::Stopped => "Stopped after the current task on request",
+            ExitReason::DeadlineReached => "Stopped at the deadline",
+            ExitReason::Interrupted => "Interrupted by user",
+            ExitReason::ConfigError => "Configuration error",
         }
-        
-        // Start user input thread only if in TTY
-        let _input_thread = if is_tty {
-            Some(thread::spawn(move || {
-                loop {
-                    // Check if we should exit
-                    if *should_exit_clone.lock().unwrap() {
-                        break;
-                    }
-                    
-                    // Check for keyboard events with a short timeout
-                    if event::poll(Duration::from_millis(50)).unwrap_or(false) {
-                        if let Ok(Event::Key(key_event)) = event::read() {
-                            // Check for Ctrl+C
-                            if matches!(key_event.code, KeyCode::Char('c')) && 
-                               key_event.modifiers.contains(KeyModifiers::CONTROL) {
-                                // Exit gracefully
-                                disable_raw_mode().ok();
-                                println!("\n\nInterrupted by user. Exiting...");
-                                std::process::exit(0);
-                            }
-                            
-                            let bytes = match key_event.code {
-                                // Arrow keys
-                                KeyCode::Up => vec![0x1B, b'[', b'A'],
-                                KeyCode::Down => vec![0x1B, b'[', b'B'],
-                                KeyCode::Right => vec![0x1B, b'[', b'C'],
-                                KeyCode::Left => vec![0x1B, b'[', b'D'],
-                                // Enter key
-                                KeyCode::Enter => vec![0x0D],
-                                // Regular characters
-                                KeyCode::Char(c) => c.to_string().into_bytes(),
-                                // Backspace
-                                KeyCode::Backspace => vec![0x7F],
-                                // Tab
-                                KeyCode::Tab => vec![0x09],
-                                // Escape
-                                KeyCode::Esc => vec![0x1B],
-                                // Other keys - ignore for now
-                                _ => vec![],
-                            };
-                            
-                            if !bytes.is_empty() {
-                                if user_tx.send(bytes).is_err() {
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                }
-            }))
+    }
+}
+
+/// Error for problems with the invocation or environment (missing task file,
+/// missing `claude` binary). `main` maps it to `ExitReason::ConfigError`.
+#[derive(Debug)]
+struct ConfigError(String);
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Machine-readable result of a run, printed by `--json-summary`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RunSummary {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    run_id: Option<String>,
+    reason: ExitReason,
+    exit_code: i32,
+    md_file: PathBuf,
+    tasks_total: usize,
+    tasks_completed: usize,
+    tasks_completed_this_run: usize,
+    continues: u32,
+    duration_secs: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Combined result of running several task files, printed by `--json-summary`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BatchSummary {
+    reason: ExitReason,
+    exit_code: i32,
+    tasks_total: usize,
+    tasks_completed: usize,
+    tasks_completed_this_run: usize,
+    continues: u32,
+    duration_secs: u64,
+    files: Vec<RunSummary>,
+}
+
+impl RunSummary {
+    /// Summary for a run that failed before or while starting Claude.
+    fn from_error(md_file: &Path, error: &anyhow::Error) -> Self {
+        let reason = if error.downcast_ref::<ConfigError>().is_some() {
+            ExitReason::ConfigError
         } else {
-            None
+            ExitReason::AgentCrashed
         };
-        
-        // Start output monitoring thread
-        println!("\n════════════════════════════════════════════════════════════");
-        println!("                      CLAUDE SESSION START                   ");
-        println!("════════════════════════════════════════════════════════════\n");
-        
-        let output_thread = thread::spawn(move || {
-            let mut buf = [0u8; 4096];
-            loop {
-                match reader.read(&mut buf) {
-                    Ok(0) => break, // EOF
-                    Ok(n) => {
-                        let output = String::from_utf8_lossy(&buf[..n]);
-                        
-                        // Print the output exactly as received
-                        print!("{}", output);
-                        std::io::stdout().flush().ok();
-                        
-                        // Update buffer
-                        let mut buffer = output_buffer_clone.lock().unwrap();
-                        buffer.push_str(&output);
-                        
-                        // Keep only recent output
-                        if buffer.len() > 2000 {
-                            // Use char_indices to respect UTF-8 boundaries
-                            let skip_chars = buffer.chars().count().saturating_sub(2000);
-                            *buffer = buffer.chars().skip(skip_chars).collect();
-                        }
-                        
-                        *last_output_time_clone.lock().unwrap() = Instant::now();
-                    }
-                    Err(e) => {
-                        if e.kind() != std::io::ErrorKind::WouldBlock {
-                            eprintln!("Read error: {}", e);
-                            break;
-                        }
-                        thread::sleep(Duration::from_millis(50));
-                    }
-                }
-            }
-        });
-        
-        // Main monitoring loop
-        loop {
-            thread::sleep(Duration::from_millis(100)); // Faster response for user input
-            
-            // Check for user input
-            if let Ok(user_bytes) = user_rx.try_recv() {
-                // User pressed a key, send raw bytes to Claude
-                writer.write_all(&user_bytes)?;
-                writer.flush()?;
-                
-                // Only reset tracking for actual character input (not just arrow keys)
-                if !user_bytes.is_empty() && user_bytes[0] != 0x1B {
-                    *self.last_output_time.lock().unwrap() = Instant::now();
-                }
+        RunSummary {
+            run_id: None,
+            reason,
+            exit_code: reason.code(),
+            md_file: md_file.to_path_buf(),
+            tasks_total: 0,
+            tasks_completed: 0,
+            tasks_completed_this_run: 0,
+            continues: 0,
+            duration_secs: 0,
+            error: Some(format!("{:#}", error)),
+        }
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let run_args = match args.command {
+        None => args.run,
+        Some(Command::Run(run_args)) => run_args,
+        Some(command) => exit_with(match command {
+            Command::Run(_) => unreachable!(),
+            Command::Validate { md_file, settings } => commands::validate(&md_file, &settings),
+            Command::List { md_file } => commands::list(&md_file),
+            Command::Init { dir, file, force } => commands::init(&dir, &file, force),
+            Command::Status { run, all, json } => commands::status(run.as_deref(), all, json),
+            Command::Report { run, output } => commands::report(run.as_deref(), output.as_deref()),
+            Command::Submit { md_files, profile, config, queue_dir } => {
+                let queue_dir = queue_dir.unwrap_or_else(daemon::default_queue_dir);
+                daemon::submit(&md_files, profile.as_deref(), config.as_deref(), &queue_dir)
             }
-            
-            // Check if process is still running
-            match child.try_wait() {
-                Ok(Some(status)) => {
-                    self.update_status(&format!("Claude process exited with status: {:?}", status));
-                    break;
-                }
-                Ok(None) => {
-                    // Process is still running
-                }
-                Err(e) => {
-                    self.update_status(&format!("Error checking process status: {}", e));
-                    break;
-                }
+            Command::Daemon { queue_dir, once, poll_interval } => {
+                let queue_dir = queue_dir.unwrap_or_else(daemon::default_queue_dir);
+                daemon::run(&queue_dir, once, poll_interval, install_interrupt_handler())
             }
-            
-            let buffer = self.output_buffer.lock().unwrap().clone();
-            let time_since_output = self.last_output_time.lock().unwrap().elapsed();
-            
-            // Check for usage limit (time shown at bottom right)
-            if let Some(wait_until) = Self::check_usage_limit(&buffer) {
-                let time_str = wait_until.format("%-I:%M%p").to_string().to_lowercase();
-                
-                if std::env::args().any(|arg| arg == "--debug" || arg == "-d") {
-                    eprintln!("[DEBUG] Usage limit detected. Wait until: {}", time_str);
-                }
-                
-                // Clear any pending output first
-                thread::sleep(Duration::from_millis(100));
-                
-                // Use eprintln to write to stderr which won't be overwritten by Claude's stdout
-                eprintln!("\n\n");
-                eprintln!("════════════════════════════════════════════════════════════");
-                eprintln!("                    USAGE LIMIT DETECTED                     ");
-                eprintln!("════════════════════════════════════════════════════════════");
-                eprintln!();
-                eprintln!("  Claude has reached its usage limit.");
-                eprintln!("  Waiting until {} to continue...", time_str);
-                eprintln!();
-                eprintln!("  This message will remain visible during the wait.");
-                eprintln!();
-                eprintln!("════════════════════════════════════════════════════════════");
-                eprintln!("\n");
-                
-                // Also print to stdout with some newlines to push Claude's output down
-                println!("\n\n\n\n\n");
-                
-                Self::wait_for_limit_reset(wait_until)?;
-                
-                *self.continue_count.lock().unwrap() += 1;
-                
-                // Clear and show resuming message (use stderr)
-                eprintln!("\n════════════════════════════════════════════════════════════");
-                eprintln!("                      RESUMING SESSION                       ");
-                eprintln!("════════════════════════════════════════════════════════════\n");
-                
-                self.update_status("Sending Continue after usage limit wait...");
-                write!(writer, "Continue")?;
-                writer.flush()?;
-                thread::sleep(Duration::from_millis(50));
-                writer.write_all(&[0x0D])?; // CR (Enter key)
-                writer.flush()?;
-                *self.output_buffer.lock().unwrap() = String::new();
-                *self.last_output_time.lock().unwrap() = Instant::now();
-                self.update_status("Claude is working...");
-                continue;
+            Command::Queue { queue_dir, json } => {
+                daemon::list(&queue_dir.unwrap_or_else(daemon::default_queue_dir), json)
             }
-            
-            // Check for bypass permissions prompt
-            if Self::check_bypass_permissions_prompt(&buffer) {
-                self.update_status("Detected bypass permissions prompt, accepting...");
-                if std::env::args().any(|arg| arg == "--debug" || arg == "-d") {
-                    eprintln!("[DEBUG] Bypass permissions prompt detected, sending '2' to accept");
-                }
-                // Send "2" to accept
-                writer.write_all(b"2")?;
-                writer.flush()?;
-                thread::sleep(Duration::from_millis(50));
-                // Send Enter key
-                writer.write_all(&[0x0D])?;
-                writer.flush()?;
-                *self.output_buffer.lock().unwrap() = String::new();
-                *self.last_output_time.lock().unwrap() = Instant::now();
-                continue;
+            Command::Config { action } => config_command(action),
+            Command::Attach { run, read_only } => {
+                state::find_run(run.as_deref()).and_then(|run_dir| attach::attach(&run_dir, read_only))
             }
-            
-            // Check if we need to send Continue
-            // Logic: If "esc to interrupt" is NOT present (Claude has stopped) AND 
-            //        we haven't had output for 60 seconds AND tasks aren't all completed
-            if time_since_output > Duration::from_secs(60) && !Self::is_claude_running(&buffer) {
-                // Check if all tasks are completed
-                if self.check_all_tasks_completed() {
-                    self.update_status("All tasks completed! Exiting...");
-                    child.kill()?;
-                    break;
-                }
-                
-                // Check for repeated patterns before sending another Continue
-                if self.check_repeated_pattern(&buffer) {
-                    self.update_status("Detected repeated pattern. Claude may be stuck. Exiting...");
-                    eprintln!("\n[ERROR] Claude appears to be stuck in a loop. Exiting to prevent infinite retries.");
-                    child.kill()?;
-                    break;
-                }
-                
-                *self.continue_count.lock().unwrap() += 1;
-                let count = *self.continue_count.lock().unwrap();
-                
-                // Also check if we've sent too many continues
-                if count > 50 {
-                    self.update_status("Maximum continue limit reached. Exiting...");
-                    eprintln!("\n[ERROR] Sent 50 Continue commands. Something may be wrong. Exiting.");
-                    child.kill()?;
-                    break;
-                }
-                
-                self.update_status(&format!("Claude stopped. Sending Continue #{}...", count));
-                write!(writer, "Continue")?;
-                writer.flush()?;
-                thread::sleep(Duration::from_millis(50));
-                writer.write_all(&[0x0D])?; // CR (Enter key)
-                writer.flush()?;
-                *self.output_buffer.lock().unwrap() = String::new();
-                *self.last_output_time.lock().unwrap() = Instant::now();
-                self.update_status("Claude is working...");
+            Command::Ctl { run, action } => {
+                let request = match action {
+                    CtlAction::Status => control::Request::Status,
+                    CtlAction::Pause => control::Request::Pause,
+                    CtlAction::Resume => control::Request::Resume,
+                    CtlAction::Inject { message } => control::Request::Inject { message },
+                    CtlAction::SkipTask { id } => control::Request::SkipTask { task: id },
+                    CtlAction::StopAfterCurrent => control::Request::StopAfterCurrent,
+                    CtlAction::Abort => control::Request::Abort,
+                };
+                commands::ctl(run.as_deref(), &request)
             }
-            // If "esc to interrupt" is present, Claude is still working - just wait
-        }
-        
-        // Signal input thread to exit
-        *should_exit.lock().unwrap() = true;
-        
-        // Drop the sender to signal input thread to stop
-        drop(writer);
-        
-        // Disable raw mode before printing final messages (only if it was enabled)
-        if is_tty {
-            disable_raw_mode().ok();
-        }
-        
-        // Wait for threads to finish
-        output_thread.join().ok();
-        // Give input thread time to exit cleanly
-        thread::sleep(Duration::from_millis(100));
-        
-        println!("\n════════════════════════════════════════════════════════════");
-        println!("                       CLAUDE SESSION END                    ");
-        println!("════════════════════════════════════════════════════════════");
-        
-        // Display final summary
-        let continues = *self.continue_count.lock().unwrap();
-        println!("\n╔═══════════════════ CLAUDIA SUMMARY ═══════════════════╗");
-        println!("║ Total Continue commands sent: {:<23} ║", continues);
-        println!("║ Session ended successfully                            ║");
-        println!("╚═══════════════════════════════════════════════════════╝\n");
-        
-        Ok(())
+        }),
+    };
+
+    DEBUG.store(run_args.debug, Ordering::Relaxed);
+    let md_files = inputs::expand(&run_args.md_files, run_args.order).unwrap_or_else(|e| exit_with(Err(e)));
+
+    if run_args.dry_run {
+        exit_with(md_files.iter().try_fold(0, |code, md_file| {
+            Ok(code.max(commands::dry_run(md_file, &run_args.settings)?))
+        }));
     }
 
+    if run_args.detach {
+        exit_with(commands::detach(&md_files));
+    }
 
-    fn check_usage_limit(buffer: &str) -> Option<DateTime<Local>> {
-        // Look for specific usage limit patterns from Claude
-        // Common patterns: "usage limit", "rate limit", "try again at", "please wait until"
-        let recent = Self::safe_suffix(buffer, 2000);
-        let recent_lower = recent.to_lowercase();
-        
-        // Check if this is actually a usage limit message
-        if !recent_lower.contains("usage limit") && 
-           !recent_lower.contains("rate limit") && 
-           !recent_lower.contains("try again") &&
-           !recent_lower.contains("please wait") {
-            return None;
-        }
-        
-        // Now look for time pattern near the usage limit message
-        let time_pattern = Regex::new(r"(\d{1,2})([:.]?\d{0,2})\s*([ap]\.?m)").ok()?;
-        
-        if let Some(captures) = time_pattern.captures(&recent_lower) {
-            let hour = captures.get(1)?.as_str().parse::<u32>().ok()?;
-            let minutes_part = captures.get(2)?.as_str();
-            let am_pm = captures.get(3)?.as_str();
-            
-            let minutes = if minutes_part.len() > 1 {
-                minutes_part.trim_start_matches(':').trim_start_matches('.').parse::<u32>().unwrap_or(0)
-            } else {
-                0
-            };
-            
-            let hour_24 = if am_pm.starts_with('p') && hour != 12 {
-                hour + 12
-            } else if am_pm.starts_with('a') && hour == 12 {
-                0
-            } else {
-                hour
-            };
-            
-            if let Some(time) = NaiveTime::from_hms_opt(hour_24, minutes, 0) {
-                let now = Local::now();
-                let mut wait_until = now.date_naive().and_time(time).and_local_timezone(Local).unwrap();
-                
-                if wait_until <= now {
-                    wait_until = wait_until + chrono::Duration::days(1);
-                }
-                
-                return Some(wait_until);
+    let interrupted = install_interrupt_handler();
+    let deadline = run_args.deadline(Local::now());
+    if let [md_file] = md_files.as_slice() {
+        let summary = match run_task_file(md_file, &run_args.settings, deadline, interrupted) {
+            Ok(summary) => summary,
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                RunSummary::from_error(md_file, &e)
             }
-        }
-        
-        None
-    }
+        };
+        send_digests(&run_args.settings, std::slice::from_ref(&summary));
 
-    fn wait_for_limit_reset(wait_until: DateTime<Local>) -> Result<()> {
-        let now = Local::now();
-        if wait_until > now {
-            let duration = wait_until - now;
-            let total_seconds = duration.num_seconds();
-            
-            // Show countdown every 30 seconds
-            let mut remaining = total_seconds;
-            while remaining > 0 {
-                let mins = remaining / 60;
-                let secs = remaining % 60;
-                
-                print!("\r  Time remaining: {:02}:{:02} ", mins, secs);
-                std::io::stdout().flush().ok();
-                
-                let sleep_duration = std::cmp::min(remaining, 30);
-                thread::sleep(Duration::from_secs(sleep_duration as u64));
-                remaining -= sleep_duration;
+        if run_args.json_summary {
+            if let Ok(json) = serde_json::to_string(&summary) {
+                println!("{}", json);
             }
-            println!("\r  Time remaining: 00:00 - Resuming now!");
         }
-        Ok(())
+        std::process::exit(summary.exit_code);
     }
 
-    fn is_claude_running(buffer: &str) -> bool {
-        // Check the last 200 chars for "esc to interrupt"
-        // If "esc to interrupt" is present, Claude is still running
-        let recent = Self::safe_suffix(buffer, 200);
-        recent.to_lowercase().contains("esc to interrupt")
+    let summary = run_task_files(&md_files, &run_args.settings, deadline, interrupted);
+    send_digests(&run_args.settings, &summary.files);
+    if run_args.json_summary {
+        if let Ok(json) = serde_json::to_string(&summary) {
+            println!("{}", json);
+        }
     }
-    
-    fn check_bypass_permissions_prompt(buffer: &str) -> bool {
-        // Check for the bypass permissions prompt
-        let recent = Self::safe_suffix(buffer, 1500);
-        let recent_lower = recent.to_lowercase();
-        
-        // Look for the characteristic prompt patterns
-        if recent_lower.contains("bypass permissions mode") &&
-           recent_lower.contains("1. no, exit") &&
-           recent_lower.contains("2. yes, i accept") {
-            return true;
+    std::process::exit(summary.exit_code);
+}
+
+/// Exit with the code returned by a subcommand, or report its error.
+fn exit_with(result: Result<i32>) -> ! {
+    let code = result.unwrap_or_else(|e| {
+        eprintln!("Error: {:#}", e);
+        if e.downcast_ref::<ConfigError>().is_some() {
+            ExitReason::ConfigError.code()
+        } else {
+            1
         }
-        
-        // Also check for variations
-        if recent.contains("WARNING: Claude Code running in Bypass Permissions mode") &&
-           (recent.contains("1. No, exit") || recent.contains("2. Yes, I accept")) {
-            return true;
+    });
+    std::process::exit(code);
+}
+
+fn config_command(action: ConfigAction) -> Result<i32> {
+    match action {
+        ConfigAction::Show { md_file, settings } => {
+            let sources = settings.sources(md_file.as_deref());
+            let resolved = sources.resolve()?;
+            config::show(&resolved);
         }
-        
-        false
     }
-    
-    fn check_all_tasks_completed(&self) -> bool {
-        // Read the markdown file and check if all checkboxes are marked
-        if let Ok(content) = fs::read_to_string(&self.md_file) {
-            // Count all checkbox patterns (-, *, +)
-            let unchecked = content.matches("[ ]").count();
-            let checked = content.matches("[x]").count() + content.matches("[X]").count();
-            
-            // If there are checkboxes and all are checked, tasks are complete
-            if checked > 0 && unchecked == 0 {
-                return true;
+    Ok(0)
+}
+
+/// Install the Ctrl+C handler. The first press sets the returned flag so the
+/// current run can shut down and report; a second press exits immediately.
+fn install_interrupt_handler() -> Arc<Mutex<bool>> {
+    let interrupted = Arc::new(Mutex::new(false));
+    let flag = Arc::clone(&interrupted);
+    ctrlc::set_handler(move || {
+        let mut flag = flag.lock().unwrap();
+        if *flag {
+            // Second Ctrl+C: don't wait for the main loop
+            if std::io::stdin().is_terminal() {
+                disable_raw_mode().ok();
             }
+            println!("\n\nInterrupted by user. Exiting...");
+            std::process::exit(ExitReason::Interrupted.code());
         }
-        false
+        *flag = true;
+    }).expect("Error setting Ctrl-C handler");
+    interrupted
+}
+
+impl RunArgs {
+    /// The earlier of `--until` and `--max-duration` from `now`.
+    fn deadline(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        let until = self.until.map(|time| schedule::next_at(time, now));
+        let max_duration = self.max_duration
+            .and_then(|d| chrono::Duration::from_std(d).ok())
+            .map(|d| now + d);
+        until.into_iter().chain(max_duration).min()
     }
-    
-    fn ensure_checkboxes(&self) -> Result<()> {
-        // Read the markdown file
-        let content = fs::read_to_string(&self.md_file)
-            .context("Failed to read markdown file")?;
-        
-        let mut modified = false;
-        let mut new_content = String::new();
-        
-        // Process each line
-        for line in content.lines() {
-            let trimmed = line.trim_start();
-            
-            // Check if this is a list item without a checkbox
-            if trimmed.starts_with("- ") || trimmed.starts_with("* ") || 
-                trimmed.starts_with("+ ") || trimmed.starts_with(char::is_numeric) {
-                
-                // Check if it already has a checkbox
-                if !trimmed.contains("- [ ]") && !trimmed.contains("- [x]") && 
-                   !trimmed.contains("- [X]") && !trimmed.contains("* [ ]") && 
-                   !trimmed.contains("* [x]") && !trimmed.contains("* [X]") {
-                    
-                    // Add checkbox after the list marker
-                    if trimmed.starts_with("- ") {
-                        new_content.push_str(&line.replace("- ", "- [ ] "));
-                        modified = true;
-                    } else if trimmed.starts_with("* ") {
-                        new_content.push_str(&line.replace("* ", "* [ ] "));
-                        modified = true;
-                    } else if trimmed.starts_with("+ ") {
-                        new_content.push_str(&line.replace("+ ", "+ [ ] "));
-                        modified = true;
-                    } else if let Some(pos) = trimmed.find(". ") {
-                        // Numbered list
-                        let (_num, rest) = trimmed.split_at(pos + 2);
-                        new_content.push_str(&format!("{}- [ ] {}", 
-                            " ".repeat(line.len() - trimmed.len()), rest));
-                        modified = true;
-                    } else {
-                        new_content.push_str(line);
-                    }
-                } else {
-                    new_content.push_str(line);
-                }
-            } else {
-                new_content.push_str(line);
-            }
-            new_content.push('\n');
-        }
-        
-        // Remove the last newline if the original didn't have one
-        if !content.ends_with('\n') && new_content.ends_with('\n') {
-            new_content.pop();
-        }
-        
-        // Write back if modified
-        if modified {
-            fs::write(&self.md_file, new_content)
-                .context("Failed to write updated markdown file")?;
-            println!("Added checkboxes to tasks in {}", self.md_file.display());
+}
+
+/// Run several task files one after another. A failed file doesn't stop the
+/// batch, but an interrupt, a deadline or a stop request does.
+fn run_task_files(
+    md_files: &[PathBuf],
+    settings: &SettingsArgs,
+    deadline: Option<DateTime<Local>>,
+    interrupted: Arc<Mutex<bool>>,
+) -> BatchSummary {
+    let started_at = std::time::Instant::now();
+    let count_tasks = |md_file: &Path| {
+        let content = std::fs::read_to_string(md_file).unwrap_or_default();
+        let tasks = tasks::parse_tasks(&tasks::normalize_checkboxes(&content).unwrap_or(content));
+        (tasks.iter().filter(|t| t.checked).count(), tasks.len())
+    };
+
+    let mut files: Vec<RunSummary> = Vec::new();
+    for (i, md_file) in md_files.iter().enumerate() {
+        // Progress so far: finished files from their summaries, the rest from disk
+        let (done, total) = files.iter().map(|s| (s.tasks_completed, s.tasks_total))
+            .chain(md_files[i..].iter().map(|f| count_tasks(f)))
+            .fold((0, 0), |(d, t), (fd, ft)| (d + fd, t + ft));
+        println!("\n▶ Task file {}/{}: {}  (overall {}/{} tasks completed)",
+            i + 1, md_files.len(), md_file.display(), done, total);
+
+        let summary = run_task_file(md_file, settings, deadline, Arc::clone(&interrupted))
+            .unwrap_or_else(|e| {
+                eprintln!("Error: {:#}", e);
+                RunSummary::from_error(md_file, &e)
+            });
+        let reason = summary.reason;
+        files.push(summary);
+        if matches!(reason, ExitReason::Interrupted | ExitReason::DeadlineReached | ExitReason::Stopped) {
+            break;
         }
-        
-        Ok(())
     }
-    
-    fn check_repeated_pattern(&self, current_buffer: &str) -> bool {
-        let mut history = self.response_history.lock().unwrap();
-        
-        // Create a normalized version of the current buffer (last 500 chars, trimmed)
-        let normalized = Self::safe_suffix(current_buffer, 500).trim().to_string();
-        
-        // Skip if empty or very short
-        if normalized.len() < 10 {
-            history.push("EMPTY_RESPONSE".to_string());
-        } else {
-            history.push(normalized);
-        }
-        
-        // Keep only last 3 responses
-        if history.len() > 3 {
-            history.remove(0);
-        }
-        
-        // Check if we have 3 identical responses
-        if history.len() >= 3 {
-            if history[0] == history[1] && history[1] == history[2] {
-                return true;
-            }
-            
-            // Also check if all 3 are empty responses
-            if history.iter().all(|h| h == "EMPTY_RESPONSE") {
-                return true;
-            }
-        }
-        
-        false
+
+    // The batch ends with the reason that stopped it, else the first failure
+    let last = files.last().map(|s| s.reason).unwrap_or(ExitReason::AllDone);
+    let reason = if matches!(last, ExitReason::Interrupted | ExitReason::DeadlineReached | ExitReason::Stopped) {
+        last
+    } else {
+        files.iter().map(|s| s.reason).find(|r| *r != ExitReason::AllDone).unwrap_or(ExitReason::AllDone)
+    };
+    let summary = BatchSummary {
+        reason,
+        exit_code: reason.code(),
+        tasks_total: files.iter().map(|s| s.tasks_total).sum(),
+        tasks_completed: files.iter().map(|s| s.tasks_completed).sum(),
+        tasks_completed_this_run: files.iter().map(|s| s.tasks_completed_this_run).sum(),
+        continues: files.iter().map(|s| s.continues).sum(),
+        duration_secs: started_at.elapsed().as_secs(),
+        files,
+    };
+
+    println!("\n╔═══════════════ CLAUDIA COMBINED SUMMARY ══════════════╗");
+    for file in &summary.files {
+        let name = file.md_file.file_name().unwrap_or_default().to_string_lossy();
+        println!("║ {:<53} ║", format!("{:<28} {:>3}/{:<3} exit {}",
+            name, file.tasks_completed, file.tasks_total, file.exit_code));
+    }
+    let skipped = md_files.len() - summary.files.len();
+    if skipped > 0 {
+        println!("║ {:<53} ║", format!("{} task file(s) not started", skipped));
     }
+    println!("║ Tasks completed: {:<36} ║", format!("{}/{} ({} this run)",
+        summary.tasks_completed, summary.tasks_total, summary.tasks_completed_this_run));
+    println!("║ Total Continue commands sent: {:<23} ║", summary.continues);
+    println!("║ {:<53} ║", reason.describe());
+    println!("║ Exit code: {:<42} ║", reason.code());
+    println!("╚═══════════════════════════════════════════════════════╝\n");
+    summary
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
-    
-    if !args.md_file.exists() {
-        anyhow::bail!("File '{}' not found", args.md_file.display());
+/// The digest settings for `md_file`, if its settings configure `email`.
+fn digest_email(md_file: &Path, settings: &SettingsArgs) -> Option<digest::Email> {
+    let email = settings.sources(Some(md_file)).resolve().ok()?.settings.email;
+    email.is_enabled().then_some(email)
+}
+
+/// Mail digests of the finished runs, each to where its task file's settings say.
+fn send_digests(settings: &SettingsArgs, runs: &[RunSummary]) {
+    let runs = runs
+        .iter()
+        .filter_map(|run| Some((digest_email(&run.md_file, settings)?, run.clone())))
+        .collect();
+    digest::send_all(runs);
+}
+
+/// Run Claude on one task file until it finishes, is stopped or fails.
+fn run_task_file(
+    md_file: &Path,
+    settings: &SettingsArgs,
+    deadline: Option<DateTime<Local>>,
+    interrupted: Arc<Mutex<bool>>,
+) -> Result<RunSummary> {
+    if !md_file.exists() {
+        return Err(ConfigError(format!("File '{}' not found", md_file.display())).into());
     }
 
-    let automator = Claudia::new(args.md_file);
-    
-    ctrlc::set_handler(move || {
-        if std::io::stdin().is_terminal() {
-            disable_raw_mode().ok();
-        }
-        println!("\n\nInterrupted by user. Exiting...");
-        std::process::exit(0);
-    }).expect("Error setting Ctrl-C handler");
+    let settings = settings.sources(Some(md_file)).resolve()?.settings;
+    let record = RunRecord::create(md_file, &settings.working_dir(md_file))?;
 
-    automator.run()?;
-    
-    Ok(())
-}
\ No newline at end of file
+    Claudia::new(md_file.to_path_buf(), settings, record, deadline, interrupted).run()
+}
@@ -0,0 +1,358 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::settings::Settings;
+use crate::state::{EventKind, RunRecord};
+use crate::template::{Template, Vars};
+use crate::ConfigError;
+
+/// Something that happened in a run that webhooks can be told about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Notice {
+    /// The run ended, for whatever reason
+    RunFinished,
+    /// Claude is stuck in a loop and the run is given up
+    Stuck,
+    /// Claude hit its usage limit; the run waits for it to reset
+    UsageLimit,
+    /// The `fail` escalation step gave up on a task
+    TaskFailed,
+}
+
+impl Notice {
+    /// Name as written in the config and sent as `event`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Notice::RunFinished => "run_finished",
+            Notice::Stuck => "stuck",
+            Notice::UsageLimit => "usage_limit",
+            Notice::TaskFailed => "task_failed",
+        }
+    }
+}
+
+/// An HTTP endpoint that is sent a POST request when something happens in a run.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Webhook {
+    pub url: String,
+    /// Events sent to this webhook; empty sends all of them
+    pub events: Vec<Notice>,
+    /// Template for the request body; empty sends the JSON payload. In a JSON
+    /// body, values are escaped to fit inside a JSON string.
+    pub body: String,
+    /// Extra request headers, e.g. `Authorization`
+    pub headers: BTreeMap<String, String>,
+}
+
+impl Webhook {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+            return Err(ConfigError(format!("Webhook URL '{}' must start with http:// or https://", self.url)));
+        }
+        Template::parse(&format!("the body of webhook {}", self.redacted_url()), &self.body)?;
+        Ok(())
+    }
+
+    /// The URL without its path and query, which often hold a secret token
+    /// (as with Slack), for logs and printed settings.
+    pub fn redacted_url(&self) -> String {
+        redact_url(&self.url)
+    }
+
+    fn wants(&self, notice: Notice) -> bool {
+        self.events.is_empty() || self.events.contains(&notice)
+    }
+
+    fn content_type(&self) -> &str {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+            .map_or("application/json", |(_, value)| value.as_str())
+    }
+
+    /// The request body for `payload`, filled in from `vars` if the webhook has a template.
+    fn render_body(&self, payload: &Payload, vars: &Vars) -> String {
+        if self.body.is_empty() {
+            return serde_json::to_string(payload).expect("payload serializes");
+        }
+        let mut vars = vars.clone();
+        if self.content_type().contains("json") {
+            for value in vars.values_mut() {
+                // Keep the quotes out so `"{{summary}}"` stays one string
+                let quoted = serde_json::to_string(value).expect("string serializes");
+                *value = quoted[1..quoted.len() - 1].to_string();
+            }
+        }
+        // Checked when the settings were validated
+        Template::parse("webhook body", &self.body).map_or_else(|_| self.body.clone(), |t| t.render(&vars))
+    }
+}
+
+/// `url` up to its host, with anything after it replaced by `/***`.
+pub fn redact_url(url: &str) -> String {
+    let host_start = url.find("://").map_or(0, |i| i + 3);
+    match url[host_start..].find(['/', '?', '#']) {
+        Some(end) if url.len() > host_start + end + 1 => format!("{}/***", &url[..host_start + end]),
+        _ => url.to_string(),
+    }
+}
+
+/// The JSON sent to a webhook without a body template.
+#[derive(Debug, Clone, Serialize)]
+pub struct Payload {
+    pub event: &'static str,
+    pub run_id: String,
+    pub task_file: String,
+    /// The task concerned: the failed task, or the one Claude was working on
+    pub task: Option<String>,
+    pub summary: String,
+    pub tasks_total: usize,
+    pub tasks_completed: usize,
+    pub continues: u32,
+    /// Only for `run_finished`
+    pub exit_code: Option<i32>,
+}
+
+/// Delivers notices to the configured webhooks in the background, so a slow
+/// endpoint never holds up the run.
+pub struct Notifier {
+    webhooks: Vec<Webhook>,
+    retries: u32,
+    timeout: Duration,
+    record: Arc<RunRecord>,
+    deliveries: Mutex<Vec<thread::JoinHandle<()>>>,
+}
+
+impl Notifier {
+    pub fn new(settings: &Settings, record: Arc<RunRecord>) -> Self {
+        Self {
+            webhooks: settings.webhooks.clone(),
+            retries: settings.webhook_retries,
+            timeout: settings.webhook_timeout,
+            record,
+            deliveries: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Send `payload` to every webhook that wants `notice`. `vars` fill in body templates.
+    pub fn send(&self, notice: Notice, payload: &Payload, vars: &Vars) {
+        for webhook in self.webhooks.iter().filter(|w| w.wants(notice)) {
+            let body = webhook.render_body(payload, vars);
+            let webhook = webhook.clone();
+            let (retries, timeout, record) = (self.retries, self.timeout, self.record.clone());
+            let delivery = thread::spawn(move || {
+                if let Err(error) = deliver(&webhook, &body, retries, timeout) {
+                    eprintln!("[WARN] Webhook {} failed: {}", webhook.redacted_url(), error);
+                    record.event(EventKind::WebhookFailed { url: webhook.redacted_url(), notice, error });
+                }
+            });
+            self.deliveries.lock().unwrap().push(delivery);
+        }
+    }
+
+    /// Wait for deliveries still in progress, e.g. before the process exits.
+    pub fn flush(&self) {
+        let deliveries = std::mem::take(&mut *self.deliveries.lock().unwrap());
+        for delivery in deliveries {
+            delivery.join().ok();
+        }
+    }
+}
+
+/// POST `body`, retrying failed attempts with a doubling pause.
+fn deliver(webhook: &Webhook, body: &str, retries: u32, timeout: Duration) -> Result<(), String> {
+    let agent = ureq::AgentBuilder::new().timeout(timeout).build();
+    let mut pause = Duration::from_secs(1);
+    let mut attempt = 0;
+    loop {
+        let mut request = agent.post(&webhook.url).set("Content-Type", webhook.content_type());
+        for (name, value) in &webhook.headers {
+            request = request.set(name, value);
+        }
+        let error = match request.send_string(body) {
+            Ok(_) => return Ok(()),
+            Err(ureq::Error::Status(code, response)) => format!("HTTP {} {}", code, response.status_text()),
+            // Transport errors quote the URL
+            Err(e) => e.to_string().replace(&webhook.url, &webhook.redacted_url()),
+        };
+        if attempt >= retries {
+            return Err(format!("{} (after {} attempts)", error, attempt + 1));
+        }
+        attempt += 1;
+        thread::sleep(pause);
+        pause *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::sync::mpsc;
+
+    /// Answer one request per status in `statuses`, passing on each request as
+    /// received (request line, lowercased headers, blank line, body). Returns
+    /// the base URL.
+    fn http_sink(statuses: Vec<u16>) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                let mut length = 0;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+                    let header = line.trim_end().to_string();
+                    let header = match header.split_once(": ") {
+                        Some((name, value)) => format!("{}: {}", name.to_lowercase(), value),
+                        None => header,
+                    };
+                    if let Some(value) = header.strip_prefix("content-length: ") {
+                        length = value.parse().unwrap();
+                    }
+                    request.push_str(&header);
+                    request.push('\n');
+                    line.clear();
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                request.push('\n');
+                request.push_str(&String::from_utf8(body).unwrap());
+                let mut writer = stream;
+                write!(writer, "HTTP/1.1 {} Sink\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+                sender.send(request).unwrap();
+            }
+        });
+        (url, receiver)
+    }
+
+    fn notifier(webhooks: Vec<Webhook>, retries: u32) -> Notifier {
+        let runs_dir = std::env::temp_dir().join(format!("claudia-notify-test-{}", std::process::id()));
+        let record = RunRecord::create_in(&runs_dir, Path::new("tasks.md"), Path::new(".")).unwrap();
+        let settings = Settings { webhooks, webhook_retries: retries, ..Settings::default() };
+        Notifier::new(&settings, Arc::new(record))
+    }
+
+    fn payload(event: &'static str) -> Payload {
+        Payload {
+            event,
+            run_id: "run-1".to_string(),
+            task_file: "tasks.md".to_string(),
+            task: Some("Write the \"parser\"".to_string()),
+            summary: "1/2 tasks done".to_string(),
+            tasks_total: 2,
+            tasks_completed: 1,
+            continues: 3,
+            exit_code: Some(0),
+        }
+    }
+
+    fn body(request: &str) -> &str {
+        request.split_once("\n\n").unwrap().1
+    }
+
+    #[test]
+    fn sends_json_payload_with_headers() {
+        let (url, requests) = http_sink(vec![200]);
+        let webhook = Webhook {
+            url: format!("{}/hook", url),
+            headers: BTreeMap::from([("Authorization".to_string(), "Bearer secret".to_string())]),
+            ..Webhook::default()
+        };
+        let notifier = notifier(vec![webhook], 0);
+        notifier.send(Notice::RunFinished, &payload("run_finished"), &Vars::new());
+        notifier.flush();
+
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1\n"));
+        assert!(request.contains("\nauthorization: Bearer secret\n"));
+        assert!(request.contains("\ncontent-type: application/json\n"));
+        let json: serde_json::Value = serde_json::from_str(body(&request)).unwrap();
+        assert_eq!(json["event"], "run_finished");
+        assert_eq!(json["task"], "Write the \"parser\"");
+        assert_eq!(json["tasks_completed"], 1);
+        assert_eq!(json["exit_code"], 0);
+    }
+
+    #[test]
+    fn escapes_values_in_json_body_template() {
+        let (url, requests) = http_sink(vec![200]);
+        let webhook = Webhook {
+            url,
+            body: r#"{"text": "{{event}}: {{summary}}"}"#.to_string(),
+            ..Webhook::default()
+        };
+        let vars = Vars::from([("event", "stuck".to_string()), ("summary", "Said \"done\"\nand\\left".to_string())]);
+        let notifier = notifier(vec![webhook.clone()], 0);
+        notifier.send(Notice::Stuck, &payload("stuck"), &vars);
+        notifier.flush();
+
+        let request = requests.recv().unwrap();
+        let json: serde_json::Value = serde_json::from_str(body(&request)).unwrap();
+        assert_eq!(json["text"], "stuck: Said \"done\"\nand\\left");
+
+        // Other bodies get the values as they are
+        let webhook = Webhook {
+            body: "{{summary}}".to_string(),
+            headers: BTreeMap::from([("Content-Type".to_string(), "text/plain".to_string())]),
+            ..webhook
+        };
+        assert_eq!(webhook.render_body(&payload("stuck"), &vars), "Said \"done\"\nand\\left");
+    }
+
+    #[test]
+    fn sends_only_the_events_a_webhook_wants() {
+        let (url, requests) = http_sink(vec![200, 200, 200]);
+        let stuck_only = Webhook { url: format!("{}/stuck", url), events: vec![Notice::Stuck], ..Webhook::default() };
+        let all = Webhook { url: format!("{}/all", url), ..Webhook::default() };
+        let notifier = notifier(vec![stuck_only, all], 0);
+        notifier.send(Notice::RunFinished, &payload("run_finished"), &Vars::new());
+        notifier.flush();
+        notifier.send(Notice::Stuck, &payload("stuck"), &Vars::new());
+        notifier.flush();
+
+        let mut received: Vec<String> = requests.iter().take(3).map(|r| r.lines().next().unwrap().to_string()).collect();
+        received.sort();
+        assert_eq!(received, ["POST /all HTTP/1.1", "POST /all HTTP/1.1", "POST /stuck HTTP/1.1"]);
+        assert!(requests.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+
+    #[test]
+    fn retries_then_records_the_failure() {
+        let (url, requests) = http_sink(vec![500, 500]);
+        let webhook = Webhook { url: format!("{}/services/T0/secret", url), ..Webhook::default() };
+        let notifier = notifier(vec![webhook], 1);
+        notifier.send(Notice::UsageLimit, &payload("usage_limit"), &Vars::new());
+        notifier.flush();
+
+        assert_eq!(requests.try_iter().count(), 2);
+        let events = state::load_events(notifier.record.dir()).unwrap();
+        let failures: Vec<_> = events
+            .iter()
+            .filter_map(|event| match &event.kind {
+                EventKind::WebhookFailed { url: failed, notice, error } => Some((failed.clone(), *notice, error.clone())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(failures, [(format!("{}/***", url), Notice::UsageLimit, "HTTP 500 Sink (after 2 attempts)".to_string())]);
+    }
+
+    #[test]
+    fn redacts_url_path_and_query() {
+        assert_eq!(redact_url("https://hooks.slack.com/services/T0/B0/XX"), "https://hooks.slack.com/***");
+        assert_eq!(redact_url("http://localhost:9000/claudia?token=1"), "http://localhost:9000/***");
+        assert_eq!(redact_url("http://localhost:9000/"), "http://localhost:9000/");
+        assert_eq!(redact_url("http://localhost:9000"), "http://localhost:9000");
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::tasks::{self, Task};
+use crate::{parent_dir, ConfigError};
+
+/// A task file of a plan.
+#[derive(Debug, Clone)]
+pub struct PlanFile {
+    /// Path as reached from the root file, for display
+    pub path: PathBuf,
+    key: PathBuf,
+    pub tasks: Vec<Task>,
+    /// Files included with `<!-- include: ... -->`
+    includes: Vec<PathBuf>,
+    /// Linked sub-plan of a task, by the task's line
+    links: HashMap<usize, PathBuf>,
+}
+
+impl PlanFile {
+    /// Sub-plan file linked from `task`, if any.
+    pub fn link(&self, task: &Task) -> Option<&Path> {
+        self.links.get(&task.line).map(PathBuf::as_path)
+    }
+}
+
+/// A task file together with the files it includes or links to, recursively.
+/// Checkbox states stay in the files themselves; the plan decides when a
+/// file (and so a task linking to it) is complete.
+#[derive(Debug, Clone)]
+pub struct Plan {
+    /// The root file first, then the others in the order they are reached
+    files: Vec<PlanFile>,
+}
+
+fn key(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+impl Plan {
+    pub fn load(root: &Path) -> Result<Self, ConfigError> {
+        let mut plan = Plan { files: Vec::new() };
+        plan.load_file(root, None, &mut Vec::new())?;
+        Ok(plan)
+    }
+
+    fn load_file(&mut self, path: &Path, from: Option<String>, stack: &mut Vec<PathBuf>) -> Result<PathBuf, ConfigError> {
+        let key = key(path);
+        if let Some(start) = stack.iter().position(|p| *p == key) {
+            let cycle: Vec<String> = stack[start..].iter().chain([&key])
+                .map(|p| p.file_name().unwrap_or_default().to_string_lossy().into_owned())
+                .collect();
+            return Err(ConfigError(format!("Task files include each other: {}", cycle.join(" -> "))));
+        }
+        if self.files.iter().any(|f| f.key == key) {
+            return Ok(key);
+        }
+
+        let content = fs::read_to_string(path).map_err(|_| match &from {
+            Some(from) => ConfigError(format!("File '{}' not found ({})", path.display(), from)),
+            None => ConfigError(format!("File '{}' not found", path.display())),
+        })?;
+        // Read list items the way they will be once they get checkboxes
+        let content = tasks::normalize_checkboxes(&content).unwrap_or(content);
+        let dir = parent_dir(path);
+        let resolve = |target: String| if dir == Path::new(".") { PathBuf::from(target) } else { dir.join(target) };
+        let index = self.files.len();
+        self.files.push(PlanFile {
+            path: path.to_path_buf(),
+            key: key.clone(),
+            tasks: tasks::parse_tasks(&content),
+            includes: Vec::new(),
+            links: HashMap::new(),
+        });
+
+        stack.push(key.clone());
+        for target in tasks::includes(&content) {
+            let from = format!("included from {}", path.display());
+            let included = self.load_file(&resolve(target), Some(from), stack)?;
+            self.files[index].includes.push(included);
+        }
+        let linked: Vec<(usize, String)> = self.files[index].tasks.iter()
+            .filter_map(|t| t.link().map(|link| (t.line, link)))
+            .collect();
+        for (line, target) in linked {
+            let from = format!("linked from {} line {}", path.display(), line + 1);
+            let sub_plan = self.load_file(&resolve(target), Some(from), stack)?;
+            self.files[index].links.insert(line, sub_plan);
+        }
+        stack.pop();
+        Ok(key)
+    }
+
+    pub fn root(&self) -> &PlanFile {
+        &self.files[0]
+    }
+
+    /// Included and linked files, without the root.
+    pub fn sub_files(&self) -> &[PlanFile] {
+        &self.files[1..]
+    }
+
+    /// Done and total tasks of a single file of the plan, linked sub-plans included.
+    pub fn file_counts(&self, path: &Path) -> (usize, usize) {
+        let checked = |t: &Task| t.checked;
+        self.file(&key(path)).map_or((0, 0), |file| {
+            let done = file.tasks.iter().filter(|t| self.is_task_done(file, t, &checked)).count();
+            (done, file.tasks.len())
+        })
+    }
+
+    fn file(&self, key: &Path) -> Option<&PlanFile> {
+        self.files.iter().find(|f| f.key == key)
+    }
+
+    /// Whether `task` of `file` is done: `is_done` says so and, for a task
+    /// linking to a sub-plan, the sub-plan is complete.
+    pub fn is_task_done(&self, file: &PlanFile, task: &Task, is_done: &dyn Fn(&Task) -> bool) -> bool {
+        is_done(task) && file.link(task).is_none_or(|sub_plan| self.is_file_complete(sub_plan, is_done))
+    }
+
+    fn is_file_complete(&self, key: &Path, is_done: &dyn Fn(&Task) -> bool) -> bool {
+        let Some(file) = self.file(key) else {
+            return true;
+        };
+        file.tasks.iter().all(|t| self.is_task_done(file, t, is_done))
+            && file.includes.iter().all(|included| self.is_file_complete(included, is_done))
+    }
+
+    /// Whether every task of the plan is done.
+    pub fn is_complete(&self, is_done: &dyn Fn(&Task) -> bool) -> bool {
+        self.is_file_complete(&self.files[0].key, is_done)
+    }
+
+    /// Done and total tasks over all files of the plan.
+    pub fn counts(&self, is_done: &dyn Fn(&Task) -> bool) -> (usize, usize) {
+        self.files.iter().fold((0, 0), |(done, total), file| {
+            let file_done = file.tasks.iter().filter(|t| self.is_task_done(file, t, is_done)).count();
+            (done + file_done, total + file.tasks.len())
+        })
+    }
+
+    /// Unchecked tasks whose linked sub-plan is complete, as (file, line).
+    pub fn completed_links(&self) -> Vec<(PathBuf, usize)> {
+        let checked = |t: &Task| t.checked;
+        self.files.iter()
+            .flat_map(|file| file.tasks.iter()
+                .filter(|t| !t.checked)
+                .filter_map(move |t| file.link(t).map(|sub_plan| (file, t, sub_plan))))
+            .filter(|(_, _, sub_plan)| self.is_file_complete(sub_plan, &checked))
+            .map(|(file, task, _)| (file.path.clone(), task.line))
+            .collect()
+    }
+}
+
+/// Check the box on line `line` of `path`.
+pub fn check_task(path: &Path, line: usize) -> std::io::Result<()> {
+    let content = fs::read_to_string(path)?;
+    fs::write(path, tasks::check(&content, line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("claudia-plan-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn names(files: &[PlanFile]) -> Vec<String> {
+        files.iter().map(|f| f.path.file_name().unwrap().to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn loads_nested_includes_once() {
+        let dir = dir("nested");
+        fs::write(dir.join("root.md"), "- [x] Root\n<!-- include: a.md -->\n<!-- include: sub/b.md -->\n").unwrap();
+        fs::write(dir.join("a.md"), "- [ ] A\n<!-- include: sub/b.md -->\n").unwrap();
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/b.md"), "- [x] B1\n- B2\n").unwrap();
+
+        let plan = Plan::load(&dir.join("root.md")).unwrap();
+        assert_eq!(names(plan.sub_files()), ["a.md", "b.md"]);
+        assert_eq!(plan.counts(&|t| t.checked), (2, 4));
+        assert_eq!(plan.file_counts(&dir.join("sub/b.md")), (1, 2));
+        assert!(!plan.is_complete(&|t| t.checked));
+        assert!(plan.is_complete(&|_| true));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rejects_an_include_cycle() {
+        let dir = dir("cycle");
+        fs::write(dir.join("root.md"), "- [ ] Root\n<!-- include: a.md -->\n").unwrap();
+        fs::write(dir.join("a.md"), "- [ ] A\n<!-- include: b.md -->\n").unwrap();
+        fs::write(dir.join("b.md"), "- [ ] B\n<!-- include: a.md -->\n").unwrap();
+
+        let err = Plan::load(&dir.join("root.md")).unwrap_err();
+        assert_eq!(err.0, "Task files include each other: a.md -> b.md -> a.md");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn names_the_file_a_missing_include_comes_from() {
+        let dir = dir("missing");
+        let root = dir.join("root.md");
+        fs::write(&root, "- [ ] Root\n<!-- include: gone.md -->\n").unwrap();
+
+        let err = Plan::load(&root).unwrap_err();
+        assert_eq!(err.0, format!("File '{}' not found (included from {})", dir.join("gone.md").display(), root.display()));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn checks_off_a_task_once_its_sub_plan_is_complete() {
+        let dir = dir("links");
+        let root = dir.join("root.md");
+        fs::write(&root, "- [x] Design\n- [ ] Build the [API](api.md)\n").unwrap();
+        fs::write(dir.join("api.md"), "- [x] Routes\n- [ ] Handle [ ] in [x] query strings\n").unwrap();
+
+        let plan = Plan::load(&root).unwrap();
+        let build = &plan.root().tasks[1];
+        assert_eq!(plan.root().link(build), Some(key(&dir.join("api.md")).as_path()));
+        assert!(!plan.is_task_done(plan.root(), build, &|t| t.checked));
+        assert_eq!(plan.file_counts(&root), (1, 2));
+        assert!(plan.completed_links().is_empty());
+
+        check_task(&dir.join("api.md"), 1).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("api.md")).unwrap(), "- [x] Routes\n- [x] Handle [ ] in [x] query strings\n");
+
+        let plan = Plan::load(&root).unwrap();
+        assert_eq!(plan.completed_links(), [(root.clone(), 1)]);
+        assert!(!plan.is_complete(&|t| t.checked));
+
+        check_task(&root, 1).unwrap();
+        let plan = Plan::load(&root).unwrap();
+        assert!(plan.completed_links().is_empty());
+        assert!(plan.is_complete(&|t| t.checked));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn checking_a_checked_task_leaves_its_text_alone() {
+        let dir = dir("check");
+        let path = dir.join("tasks.md");
+        fs::write(&path, "1. [x] Keep [ ] as it is\n").unwrap();
+
+        check_task(&path, 0).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "1. [x] Keep [ ] as it is\n");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use crate::git;
+use crate::settings::EscalationStep;
+
+/// Directories skipped when fingerprinting a directory that isn't a git repository.
+const SKIPPED_DIRS: &[&str] = &["target", "node_modules", "__pycache__"];
+
+/// Give up on walking a directory with more files than this.
+const MAX_FILES: usize = 50_000;
+
+/// A value that changes whenever files change in `dir`, ignoring the file
+/// `exclude` (the task file). Uses git in a repository, and the names, sizes
+/// and modification times of the files otherwise.
+pub fn tree_fingerprint(dir: &Path, exclude: &Path) -> Option<u64> {
+    if let Some(fingerprint) = git::tree_fingerprint(dir, exclude) {
+        return Some(fingerprint);
+    }
+    let exclude = exclude.canonicalize().ok();
+    let mut hasher = DefaultHasher::new();
+    let mut pending = vec![dir.to_path_buf()];
+    let mut files = 0;
+    while let Some(current) = pending.pop() {
+        let mut entries: Vec<_> = fs::read_dir(&current).ok()?.flatten().collect();
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Ok(meta) = entry.metadata() else { continue };
+            if meta.is_dir() {
+                if !name.starts_with('.') && !SKIPPED_DIRS.contains(&name.as_str()) {
+                    pending.push(path);
+                }
+                continue;
+            }
+            if exclude.as_deref().is_some_and(|exclude| path.canonicalize().is_ok_and(|p| p == exclude)) {
+                continue;
+            }
+            files += 1;
+            if files > MAX_FILES {
+                return None;
+            }
+            path.hash(&mut hasher);
+            meta.len().hash(&mut hasher);
+            meta.modified().ok().hash(&mut hasher);
+        }
+    }
+    Some(hasher.finish())
+}
+
+/// What counts as progress: the working tree, and the completed count, total
+/// and current task of the task file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Snapshot {
+    pub tree: Option<u64>,
+    pub tasks: (usize, usize, Option<String>),
+}
+
+/// Counts the Continues sent in a row without progress.
+#[derive(Debug, Default)]
+pub struct StallTracker {
+    last: Option<Snapshot>,
+    idle_continues: u32,
+}
+
+/// Which signs of progress were missing when a stall was detected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stall {
+    pub continues: u32,
+    /// False if the directory could not be fingerprinted
+    pub files_watched: bool,
+}
+
+impl StallTracker {
+    /// Record the state as Claude stopped again. Returns a stall once
+    /// `limit` Continues in a row (0 = never) went by without progress;
+    /// the count then starts over.
+    pub fn observe(&mut self, snapshot: Snapshot, limit: u32) -> Option<Stall> {
+        let files_watched = snapshot.tree.is_some();
+        if self.last.as_ref() == Some(&snapshot) {
+            self.idle_continues += 1;
+        } else {
+            self.idle_continues = 0;
+            self.last = Some(snapshot);
+        }
+        if limit == 0 || self.idle_continues < limit {
+            return None;
+        }
+        let continues = std::mem::take(&mut self.idle_continues);
+        Some(Stall { continues, files_watched })
+    }
+}
+
+impl Stall {
+    /// E.g. "5 Continues, no file changes, no task progress".
+    pub fn describe(&self) -> String {
+        if self.files_watched {
+            format!("{} Continues, no file changes, no task progress", self.continues)
+        } else {
+            format!("{} Continues, no task progress", self.continues)
+        }
+    }
+}
+
+/// Where the escalation ladder stands: the next step, and the progress
+/// (completed count and current task) when it last moved.
+#[derive(Debug, Default)]
+pub struct Escalation {
+    position: usize,
+    mark: (usize, Option<String>),
+}
+
+impl Escalation {
+    /// The next step of `ladder`, which must not be empty. Progress since the
+    /// last step starts the ladder over, and the last step repeats. When
+    /// `stuck`, steps that only send a message are skipped; `None` if no
+    /// other step is left.
+    pub fn next_step(
+        &mut self,
+        ladder: &[EscalationStep],
+        progress: (usize, Option<String>),
+        stuck: bool,
+    ) -> Option<EscalationStep> {
+        if self.mark != progress {
+            self.mark = progress;
+            self.position = 0;
+        }
+        self.position = self.position.min(ladder.len() - 1);
+        if stuck {
+            self.position += ladder[self.position..]
+                .iter()
+                .position(|step| !matches!(step, EscalationStep::Continue | EscalationStep::Firm))?;
+        }
+        let step = ladder[self.position];
+        self.position += 1;
+        Some(step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use EscalationStep::*;
+
+    fn on(completed: usize, task: &str) -> (usize, Option<String>) {
+        (completed, Some(task.to_string()))
+    }
+
+    #[test]
+    fn escalation_climbs_and_repeats_the_last_step() {
+        let ladder = [Continue, Firm, Clear, Fail];
+        let mut escalation = Escalation::default();
+        let steps: Vec<_> = (0..6).filter_map(|_| escalation.next_step(&ladder, on(1, "A"), false)).collect();
+        assert_eq!(steps, [Continue, Firm, Clear, Fail, Fail, Fail]);
+    }
+
+    #[test]
+    fn escalation_starts_over_on_progress() {
+        let ladder = [Continue, Firm, Restart];
+        let mut escalation = Escalation::default();
+        assert_eq!(escalation.next_step(&ladder, on(1, "A"), false), Some(Continue));
+        assert_eq!(escalation.next_step(&ladder, on(1, "A"), false), Some(Firm));
+        // A task was checked off
+        assert_eq!(escalation.next_step(&ladder, on(2, "B"), false), Some(Continue));
+        assert_eq!(escalation.next_step(&ladder, on(2, "B"), false), Some(Firm));
+        // Claude moved on to another task without checking any
+        assert_eq!(escalation.next_step(&ladder, on(2, "C"), false), Some(Continue));
+        assert_eq!(escalation.next_step(&ladder, on(2, "C"), false), Some(Firm));
+        assert_eq!(escalation.next_step(&ladder, on(2, "C"), false), Some(Restart));
+    }
+
+    #[test]
+    fn escalation_skips_messages_when_stuck() {
+        let ladder = [Continue, Firm, Clear, Continue, Restart];
+        let mut escalation = Escalation::default();
+        assert_eq!(escalation.next_step(&ladder, on(0, "A"), true), Some(Clear));
+        assert_eq!(escalation.next_step(&ladder, on(0, "A"), false), Some(Continue));
+        assert_eq!(escalation.next_step(&ladder, on(0, "A"), true), Some(Restart));
+        assert_eq!(escalation.next_step(&ladder, on(0, "A"), true), Some(Restart));
+        assert_eq!(Escalation::default().next_step(&[Continue, Firm], on(0, "A"), true), None);
+    }
+}
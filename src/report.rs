@@ -0,0 +1,165 @@
+use std::fmt::Write;
+
+use crate::progress::Stall;
+use crate::state::{Event, EventKind, RunState};
+
+/// Format a number of seconds as e.g. `1h 05m`, `4m 10s` or `12s`.
+pub fn format_elapsed(secs: u64) -> String {
+    let (h, m, s) = (secs / 3600, (secs % 3600) / 60, secs % 60);
+    if h > 0 {
+        format!("{}h {:02}m", h, m)
+    } else if m > 0 {
+        format!("{}m {:02}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
+}
+
+/// Seconds between events matching `starts` and the next event matching `ends`
+/// (or the end of the run).
+fn waited_secs(events: &[Event], starts: fn(&EventKind) -> bool, ends: fn(&EventKind) -> bool) -> u64 {
+    let mut total = 0;
+    let mut waiting_since = None;
+    for event in events {
+        if starts(&event.kind) {
+            waiting_since = Some(event.time);
+        } else if ends(&event.kind) || matches!(event.kind, EventKind::RunFinished { .. }) {
+            if let Some(since) = waiting_since.take() {
+                total += (event.time - since).num_seconds().max(0) as u64;
+            }
+        }
+    }
+    total
+}
+
+/// Seconds spent waiting for usage limits to reset, from the event log.
+pub fn limit_wait_secs(events: &[Event]) -> u64 {
+    waited_secs(events, |e| matches!(e, EventKind::UsageLimit { .. }), |e| matches!(e, EventKind::Resumed))
+}
+
+/// Seconds spent waiting for a run window to open, from the event log.
+pub fn window_wait_secs(events: &[Event]) -> u64 {
+    waited_secs(events, |e| matches!(e, EventKind::WindowClosed { .. }), |e| matches!(e, EventKind::WindowOpened))
+}
+
+fn describe_event(kind: &EventKind) -> Option<String> {
+    Some(match kind {
+        EventKind::RunStarted { tasks_total, tasks_completed, .. } => {
+            format!("Run started ({}/{} tasks already completed)", tasks_completed, tasks_total)
+        }
+        EventKind::ContinueSent { count } => format!("Continue #{} sent", count),
+        EventKind::UsageLimit { until } => format!("Usage limit reached, waiting until {}", until.format("%H:%M")),
+        EventKind::Resumed => "Resumed after usage limit".to_string(),
+        EventKind::WindowClosed { until } => {
+            format!("Outside run windows, waiting until {}", until.format("%a %H:%M"))
+        }
+        EventKind::WindowOpened => "Run window opened".to_string(),
+        EventKind::WrapUpRequested { deadline } => {
+            format!("Asked Claude to wrap up before the {} deadline", deadline.format("%H:%M"))
+        }
+        EventKind::TaskCompleted { text } => format!("Task completed: {}", text),
+        EventKind::TasksAdded { tasks } => format!("Task file edited, new tasks: {}", tasks.join("; ")),
+        EventKind::Escalated { step, task } => format!("{} ({})", step.describe(), task),
+        EventKind::TaskFailed { text } => format!("Task failed: {}", text),
+        EventKind::VerifyPassed { command } => format!("Verification passed: {}", command),
+        EventKind::VerifyFailed { command, status } => format!("Verification failed ({}): {}", status, command),
+        EventKind::AgentExited { status } => format!("Claude exited: {}", status),
+        EventKind::AgentRestarted { attempt, delay_secs } => {
+            format!("Restarting Claude (attempt {}) after {}", attempt, format_elapsed(*delay_secs))
+        }
+        EventKind::WebhookFailed { url, notice, error } => {
+            format!("Webhook {} for {} failed: {}", url, notice.name(), error)
+        }
+        EventKind::HookFailed { hook, error } => format!("Hook {} failed: {}", hook, error),
+        EventKind::TasksRestored { changes } => format!("Task list edit undone: {}", changes.join("; ")),
+        EventKind::SuspiciousCompletion { text, reasons } => {
+            format!("Suspicious completion of \"{}\": {}", text, reasons.join(" and "))
+        }
+        EventKind::Stalled { continues, files_watched } => {
+            format!("Stalled: {}", Stall { continues: *continues, files_watched: *files_watched }.describe())
+        }
+        EventKind::ControlCommand { command } => format!("Control request: {}", command),
+        EventKind::MessageInjected { message } => format!("Message sent to Claude: {}", message),
+        EventKind::RunFinished { reason, exit_code, .. } => {
+            format!("Run finished: {} (exit {})", reason.describe(), exit_code)
+        }
+        // Status lines are mostly "Claude is working..." and would drown the timeline
+        EventKind::Status { .. } => return None,
+    })
+}
+
+/// Render a Markdown report of a run.
+pub fn render(run: &RunState, events: &[Event]) -> String {
+    let mut out = String::new();
+    let duration = (run.updated_at - run.started_at).num_seconds().max(0) as u64;
+    let initial_completed = events.iter().find_map(|e| match &e.kind {
+        EventKind::RunStarted { tasks_completed, .. } => Some(*tasks_completed),
+        _ => None,
+    });
+
+    writeln!(out, "# Claudia run {}\n", run.run_id).ok();
+    writeln!(out, "- **Task file:** `{}`", run.md_file.display()).ok();
+    writeln!(out, "- **Started:** {}", run.started_at.format("%Y-%m-%d %H:%M:%S")).ok();
+    writeln!(out, "- **Last update:** {}", run.updated_at.format("%Y-%m-%d %H:%M:%S")).ok();
+    writeln!(out, "- **Duration:** {}", format_elapsed(duration)).ok();
+    let result = match (run.reason, run.exit_code) {
+        (Some(reason), Some(code)) => format!("{} (exit {})", reason.describe(), code),
+        _ if run.is_stale() => "Unknown (the run stopped without finishing)".to_string(),
+        _ => format!("Still running: {}", run.status),
+    };
+    writeln!(out, "- **Result:** {}", result).ok();
+    match initial_completed {
+        Some(initial) => writeln!(out, "- **Tasks:** {}/{} completed ({} this run)",
+            run.tasks_completed, run.tasks_total, run.tasks_completed.saturating_sub(initial)).ok(),
+        None => writeln!(out, "- **Tasks:** {}/{} completed", run.tasks_completed, run.tasks_total).ok(),
+    };
+    writeln!(out, "- **Continues sent:** {}", run.continues).ok();
+    let restarts = events.iter().filter(|e| matches!(e.kind, EventKind::AgentRestarted { .. })).count();
+    if restarts > 0 {
+        writeln!(out, "- **Restarts after Claude exited:** {}", restarts).ok();
+    }
+    writeln!(out, "- **Time waiting on usage limits:** {}", format_elapsed(limit_wait_secs(events))).ok();
+    let window_wait = window_wait_secs(events);
+    if window_wait > 0 {
+        writeln!(out, "- **Time outside run windows:** {}", format_elapsed(window_wait)).ok();
+    }
+
+    let completed: Vec<_> = events
+        .iter()
+        .filter_map(|e| match &e.kind {
+            EventKind::TaskCompleted { text } => Some((e.time, text)),
+            _ => None,
+        })
+        .collect();
+    if !completed.is_empty() {
+        writeln!(out, "\n## Completed Tasks\n").ok();
+        for (time, text) in completed {
+            writeln!(out, "- {} {}", time.format("%H:%M"), text).ok();
+        }
+    }
+
+    let failed: Vec<_> = events
+        .iter()
+        .filter_map(|e| match &e.kind {
+            EventKind::TaskFailed { text } => Some((e.time, text)),
+            _ => None,
+        })
+        .collect();
+    if !failed.is_empty() {
+        writeln!(out, "\n## Failed Tasks\n").ok();
+        for (time, text) in failed {
+            writeln!(out, "- {} {}", time.format("%H:%M"), text).ok();
+        }
+    }
+
+    writeln!(out, "\n## Timeline\n").ok();
+    writeln!(out, "| Time | Event |").ok();
+    writeln!(out, "|------|-------|").ok();
+    for event in events {
+        if let Some(description) = describe_event(&event.kind) {
+            writeln!(out, "| {} | {} |", event.time.format("%Y-%m-%d %H:%M:%S"),
+                description.replace('|', "\\|")).ok();
+        }
+    }
+    out
+}
@@ -0,0 +1,279 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, Local, NaiveTime, Timelike};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::state;
+
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// A weekly period during which claudia may work, e.g. `Mon-Fri 22:00-07:00`.
+/// The days name the day a window starts on, so an overnight window that
+/// starts on Friday runs into Saturday morning. Without days it applies daily.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Window {
+    spec: String,
+    /// Indexed by days from Monday
+    days: [bool; 7],
+    /// Minutes after midnight
+    start: u32,
+    end: u32,
+}
+
+impl Window {
+    /// Whether `time` falls inside the window.
+    pub fn contains(&self, time: DateTime<Local>) -> bool {
+        let minute = time.hour() * 60 + time.minute();
+        let today = time.weekday().num_days_from_monday() as usize;
+        if self.start < self.end {
+            return self.days[today] && (self.start..self.end).contains(&minute);
+        }
+        // Overnight: the evening part belongs to today, the morning part to yesterday
+        let yesterday = (today + 6) % 7;
+        (self.days[today] && minute >= self.start) || (self.days[yesterday] && minute < self.end)
+    }
+
+    /// The first time after `time` at which the window opens.
+    pub fn next_open(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = NaiveTime::from_hms_opt(self.start / 60, self.start % 60, 0)?;
+        (0..=7)
+            .map(|days| time.date_naive() + ChronoDuration::days(days))
+            .filter(|date| self.days[date.weekday().num_days_from_monday() as usize])
+            .filter_map(|date| date.and_time(start).and_local_timezone(Local).earliest())
+            .find(|open| *open > time)
+    }
+}
+
+fn parse_day(s: &str) -> Option<usize> {
+    let s = s.trim().to_lowercase();
+    DAY_NAMES.iter().position(|day| s.starts_with(day))
+}
+
+fn parse_time(s: &str) -> Option<u32> {
+    let (h, m) = s.trim().split_once(':')?;
+    let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+    (m < 60 && (h < 24 || (h == 24 && m == 0))).then_some(h * 60 + m)
+}
+
+fn parse_days(s: &str) -> Option<[bool; 7]> {
+    let mut days = [false; 7];
+    for part in s.split(',') {
+        match part.trim().to_lowercase().as_str() {
+            "daily" => days = [true; 7],
+            "weekdays" => days[..5].iter_mut().for_each(|d| *d = true),
+            "weekends" => days[5..].iter_mut().for_each(|d| *d = true),
+            part => match part.split_once('-') {
+                // Ranges may wrap around the week, e.g. Fri-Mon
+                Some((from, to)) => {
+                    let (from, to) = (parse_day(from)?, parse_day(to)?);
+                    let mut day = from;
+                    loop {
+                        days[day] = true;
+                        if day == to {
+                            break;
+                        }
+                        day = (day + 1) % 7;
+                    }
+                }
+                None => days[parse_day(part)?] = true,
+            },
+        }
+    }
+    Some(days)
+}
+
+impl FromStr for Window {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let invalid = || format!("invalid run window '{}' (expected e.g. 'Mon-Fri 22:00-07:00')", spec);
+        let (days, times) = match spec.trim().rsplit_once(char::is_whitespace) {
+            Some((days, times)) => (parse_days(days).with_context(invalid)?, times),
+            None => ([true; 7], spec.trim()),
+        };
+        let (start, end) = times.split_once('-').with_context(invalid)?;
+        let (start, end) = (parse_time(start).with_context(invalid)?, parse_time(end).with_context(invalid)?);
+        if start == end || start == 24 * 60 {
+            anyhow::bail!("{}: the window is empty", invalid());
+        }
+        Ok(Self { spec: spec.trim().to_string(), days, start, end })
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.spec)
+    }
+}
+
+impl Serialize for Window {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Window {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Parse a time of day such as `07:30`.
+pub fn parse_time_of_day(s: &str) -> Result<NaiveTime> {
+    parse_time(s)
+        .filter(|minutes| *minutes < 24 * 60)
+        .and_then(|minutes| NaiveTime::from_hms_opt(minutes / 60, minutes % 60, 0))
+        .with_context(|| format!("invalid time '{}' (expected HH:MM)", s))
+}
+
+/// The next time after `now` at which the clock shows `time`: today or tomorrow.
+pub fn next_at(time: NaiveTime, now: DateTime<Local>) -> DateTime<Local> {
+    (0..=2)
+        .map(|days| now.date_naive() + ChronoDuration::days(days))
+        .filter_map(|date| date.and_time(time).and_local_timezone(Local).earliest())
+        .find(|at| *at > now)
+        .unwrap_or(now)
+}
+
+/// Why a run is not allowed to work right now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WaitReason {
+    UsageLimit,
+    OutsideWindow,
+}
+
+impl WaitReason {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            WaitReason::UsageLimit => "waiting for usage limit",
+            WaitReason::OutsideWindow => "outside run windows",
+        }
+    }
+}
+
+/// When the run may next work, if not now: after a usage limit (`usage_limit`
+/// or one recorded by another run) has reset and inside one of `windows`.
+/// With no windows configured, any time of day is allowed.
+pub fn blocked_until(
+    windows: &[Window],
+    usage_limit: Option<DateTime<Local>>,
+    now: DateTime<Local>,
+) -> Option<(DateTime<Local>, WaitReason)> {
+    let limit = usage_limit.max(state::active_usage_limit()).filter(|until| *until > now);
+    if let Some(until) = limit {
+        return Some((until, WaitReason::UsageLimit));
+    }
+    if windows.is_empty() || windows.iter().any(|w| w.contains(now)) {
+        return None;
+    }
+    windows
+        .iter()
+        .filter_map(|w| w.next_open(now))
+        .min()
+        .map(|until| (until, WaitReason::OutsideWindow))
+}
+
+/// Sleep until `until`, showing a countdown. Returns early if interrupted.
+pub fn wait_until(until: DateTime<Local>, interrupted: &Mutex<bool>) -> Result<()> {
+    let now = Local::now();
+    if until > now {
+        let duration = until - now;
+        let total_seconds = duration.num_seconds();
+
+        // Show countdown every 30 seconds
+        let mut remaining = total_seconds;
+        while remaining > 0 {
+            let (hours, mins, secs) = (remaining / 3600, (remaining % 3600) / 60, remaining % 60);
+            if hours > 0 {
+                print!("\r  Time remaining: {}:{:02}:{:02} ", hours, mins, secs);
+            } else {
+                print!("\r  Time remaining: {:02}:{:02} ", mins, secs);
+            }
+            std::io::stdout().flush().ok();
+
+            // Sleep in one-second steps so Ctrl+C is noticed promptly
+            let sleep_duration = std::cmp::min(remaining, 30);
+            for _ in 0..sleep_duration {
+                if *interrupted.lock().unwrap() {
+                    return Ok(());
+                }
+                thread::sleep(Duration::from_secs(1));
+            }
+            remaining -= sleep_duration;
+        }
+        println!("\r  Time remaining: 00:00 - Resuming now!");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// A time in the week of Monday, 5 October 2026; `day` 0 is that Monday.
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2026, 10, 5 + day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn parses_windows() {
+        let window: Window = "Mon-Fri 22:00-07:00".parse().unwrap();
+        assert_eq!(window.days, [true, true, true, true, true, false, false]);
+        assert_eq!((window.start, window.end), (22 * 60, 7 * 60));
+        assert_eq!(window.to_string(), "Mon-Fri 22:00-07:00");
+        assert_eq!("weekends 9:00-17:30".parse::<Window>().unwrap().days, [false, false, false, false, false, true, true]);
+        assert_eq!("Fri-Mon 10:00-11:00".parse::<Window>().unwrap().days, [true, false, false, false, true, true, true]);
+        assert_eq!("Tue,thursday 10:00-24:00".parse::<Window>().unwrap().days, [false, true, false, true, false, false, false]);
+        assert_eq!("22:00-07:00".parse::<Window>().unwrap().days, [true; 7]);
+    }
+
+    #[test]
+    fn rejects_invalid_windows() {
+        for spec in ["Mon-Fri", "Funday 10:00-11:00", "10:00", "10:00-10:00", "25:00-26:00", "24:00-07:00", "10:60-11:00"] {
+            assert!(spec.parse::<Window>().is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn daytime_window_contains_its_hours_on_its_days() {
+        let window: Window = "weekdays 09:00-17:00".parse().unwrap();
+        assert!(window.contains(at(0, 9, 0)));
+        assert!(window.contains(at(4, 16, 59)));
+        assert!(!window.contains(at(0, 17, 0)));
+        assert!(!window.contains(at(0, 8, 59)));
+        assert!(!window.contains(at(5, 12, 0)));
+    }
+
+    #[test]
+    fn overnight_window_runs_past_midnight_into_the_next_day() {
+        let window: Window = "Mon-Fri 22:00-07:00".parse().unwrap();
+        assert!(window.contains(at(0, 22, 0)));
+        assert!(window.contains(at(0, 23, 59)));
+        // Monday night's window is open on Tuesday morning
+        assert!(window.contains(at(1, 3, 0)));
+        assert!(!window.contains(at(1, 7, 0)));
+        assert!(!window.contains(at(1, 12, 0)));
+        // Friday night's runs into Saturday, but none starts on Saturday or Sunday
+        assert!(window.contains(at(5, 6, 59)));
+        assert!(!window.contains(at(5, 22, 30)));
+        assert!(!window.contains(at(6, 23, 0)));
+        // Monday morning belongs to Sunday night
+        assert!(!window.contains(at(0, 3, 0)));
+    }
+
+    #[test]
+    fn next_open_is_the_next_start_on_a_window_day() {
+        let window: Window = "Mon-Fri 22:00-07:00".parse().unwrap();
+        assert_eq!(window.next_open(at(1, 8, 0)), Some(at(1, 22, 0)));
+        assert_eq!(window.next_open(at(1, 22, 0)), Some(at(2, 22, 0)));
+        // From Friday night to Monday night
+        assert_eq!(window.next_open(at(4, 23, 0)), Some(at(7, 22, 0)));
+    }
+}
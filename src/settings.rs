@@ -0,0 +1,512 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::digest::Email;
+use crate::hooks::Hooks;
+use crate::notify::Webhook;
+use crate::schedule::Window;
+use crate::template::Template;
+use crate::{parent_dir, ConfigError};
+
+/// Settings for a run, resolved from defaults, config files, profiles, CLI
+/// flags and (for some values) per-task overrides.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// Command used to start the agent
+    pub agent_command: String,
+    /// Arguments passed to the agent command
+    pub agent_args: Vec<String>,
+    /// Model passed to the agent with `--model`; empty uses the agent's default
+    pub model: String,
+    /// Directory the agent runs in, relative to the task file; empty means
+    /// the task file's directory
+    pub working_dir: String,
+    /// Shell command that must succeed before the tasks count as done; empty
+    /// skips verification
+    pub verify_command: String,
+    /// How long Claude must be quiet before a Continue is considered
+    #[serde(with = "duration_format")]
+    pub idle_timeout: Duration,
+    /// Don't count Claude as idle while its processes use the CPU or disk
+    /// (read from /proc on Linux)
+    pub watch_processes: bool,
+    /// CPU use of Claude's process tree, in percent of one core, that counts as busy
+    pub busy_cpu_percent: u32,
+    /// Stop waiting for busy processes after this long
+    #[serde(with = "duration_format")]
+    pub max_busy_wait: Duration,
+    /// Maximum number of Continue commands per run
+    pub max_continues: u32,
+    /// Number of consecutive responses compared by the loop detector
+    pub loop_window: usize,
+    /// Number of trailing characters of each response the loop detector compares
+    pub loop_compare_chars: usize,
+    /// How similar (0-1) normalized responses must be to count as repeats
+    pub loop_similarity: f64,
+    /// Continues in a row without file changes or task progress before Claude
+    /// counts as stuck; 0 turns this off
+    pub stall_continues: u32,
+    /// Number of characters of recent output kept for pattern detection
+    pub output_buffer_chars: usize,
+    /// Undo edits that change the existing tasks other than their checkboxes
+    pub guard_tasks: bool,
+    /// What to do about a task that looks checked off without being done
+    pub suspicious_policy: SuspiciousPolicy,
+    /// Checking off at least this many tasks in one edit is suspicious
+    pub suspicious_batch: usize,
+    /// Template sent when a suspicious task is reopened
+    pub challenge_prompt: String,
+    /// Message typed when Claude has stopped before finishing (a template)
+    pub continue_message: String,
+    /// Template for the initial prompt; empty uses the built-in prompt
+    pub prompt_template: String,
+    /// File holding the initial prompt template, relative to the task file;
+    /// takes precedence over `prompt_template`
+    pub prompt_template_file: String,
+    /// Template typed instead of the Continue message when Claude has moved on
+    /// to another task since the last prompt; empty always sends the Continue message
+    pub task_prompt: String,
+    /// Steps taken, in order, each time Claude stops without progress on its
+    /// task; the last one repeats
+    pub escalation: Vec<EscalationStep>,
+    /// Template for the `firm` step
+    pub firm_message: String,
+    /// Template sent after `/clear` in the `clear` step
+    pub scoped_prompt: String,
+    /// Template sent after the `fail` step has given up on a task
+    pub failed_message: String,
+    /// How often Claude is started again when it exits with tasks still open
+    pub max_restarts: u32,
+    /// Whether a restarted Claude resumes its conversation with `--continue`
+    pub restart_resume: bool,
+    /// Wait before restarting Claude; doubled for each exit in quick succession
+    #[serde(with = "duration_format")]
+    pub restart_backoff: Duration,
+    /// Extra instructions appended to the initial prompt
+    pub rules: Vec<String>,
+    /// When Claude may work, e.g. `Mon-Fri 22:00-07:00`; empty means any time
+    pub run_windows: Vec<Window>,
+    /// How long before a deadline Claude is asked to wrap up
+    #[serde(with = "duration_format")]
+    pub wrap_up_time: Duration,
+    /// Message typed when it is time to wrap up before a deadline
+    pub wrap_up_message: String,
+    /// Endpoints told about finished runs, loops, usage limits and failed tasks
+    pub webhooks: Vec<Webhook>,
+    /// How often a failed webhook request is retried
+    pub webhook_retries: u32,
+    /// How long a webhook request may take
+    #[serde(with = "duration_format")]
+    pub webhook_timeout: Duration,
+    /// Shell commands run at points of the run
+    pub hooks: Hooks,
+    /// How long a hook may run before it is stopped
+    #[serde(with = "duration_format")]
+    pub hook_timeout: Duration,
+    /// SMTP settings for the digest mailed when the run ends
+    pub email: Email,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            agent_command: "claude".to_string(),
+            agent_args: vec!["--dangerously-skip-permissions".to_string()],
+            model: String::new(),
+            working_dir: String::new(),
+            verify_command: String::new(),
+            idle_timeout: Duration::from_secs(60),
+            watch_processes: true,
+            busy_cpu_percent: 10,
+            max_busy_wait: Duration::from_secs(3600),
+            max_continues: 50,
+            loop_window: 3,
+            loop_compare_chars: 500,
+            loop_similarity: 0.9,
+            stall_continues: 5,
+            output_buffer_chars: 2000,
+            guard_tasks: true,
+            suspicious_policy: SuspiciousPolicy::Warn,
+            suspicious_batch: 3,
+            challenge_prompt: "You checked off \"{{suspect_task}}\", but {{suspicion}}, so it has been unchecked again. Check whether it is really done: do whatever is missing, show the evidence (changed files, command output), and only then mark it [x].".to_string(),
+            continue_message: "Continue{{#if next_tasks}} with the next unchecked tasks: {{next_tasks}}{{/if}}".to_string(),
+            prompt_template: String::new(),
+            prompt_template_file: String::new(),
+            task_prompt: String::new(),
+            escalation: vec![
+                EscalationStep::Continue,
+                EscalationStep::Continue,
+                EscalationStep::Firm,
+                EscalationStep::Clear,
+                EscalationStep::Restart,
+                EscalationStep::Fail,
+            ],
+            firm_message: "You have stopped without finishing. These tasks in {{file_path}} are still open:\n{{remaining_tasks}}\nWork on \"{{current_task}}\" now and mark it [x] in the file when it is done.".to_string(),
+            scoped_prompt: "Work on this task from {{file_path}}{{#if section}} (section \"{{section}}\"){{/if}}: {{current_task}}\nWhen it is done, change its [ ] to [x] in the file, then continue with the next unchecked task.".to_string(),
+            failed_message: "Stop working on \"{{failed_task}}\"; it has been marked as failed. Leave it unchecked{{#if current_task}} and continue with the next task: {{current_task}}{{/if}}.".to_string(),
+            max_restarts: 3,
+            restart_resume: true,
+            restart_backoff: Duration::from_secs(5),
+            rules: Vec::new(),
+            run_windows: Vec::new(),
+            wrap_up_time: Duration::from_secs(10 * 60),
+            wrap_up_message: "We are almost out of time. Finish the step you are on, make sure completed tasks are marked [x], commit your work, and then stop without starting a new task.".to_string(),
+            webhooks: Vec::new(),
+            webhook_retries: 3,
+            webhook_timeout: Duration::from_secs(10),
+            hooks: Hooks::default(),
+            hook_timeout: Duration::from_secs(5 * 60),
+            email: Email::default(),
+        }
+    }
+}
+
+/// What claudia does about a task that looks checked off without being done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuspiciousPolicy {
+    /// Don't look
+    Off,
+    /// Print a warning and log it
+    Warn,
+    /// Uncheck the task and send `challenge_prompt`
+    Reopen,
+    /// Run `verify_command`; uncheck the task if it fails
+    Verify,
+}
+
+/// What claudia does when Claude has stopped before finishing, see `escalation`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EscalationStep {
+    /// Send `continue_message` (or `task_prompt`)
+    Continue,
+    /// Send `firm_message`, which quotes the open tasks
+    Firm,
+    /// Clear the conversation with `/clear`, then send `scoped_prompt`
+    Clear,
+    /// Restart Claude and send the initial prompt again
+    Restart,
+    /// Mark the current task as failed, then send `failed_message`
+    Fail,
+}
+
+impl EscalationStep {
+    /// Name as written in the config.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EscalationStep::Continue => "continue",
+            EscalationStep::Firm => "firm",
+            EscalationStep::Clear => "clear",
+            EscalationStep::Restart => "restart",
+            EscalationStep::Fail => "fail",
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            EscalationStep::Continue => "Sending Continue",
+            EscalationStep::Firm => "Sending a firmer reminder",
+            EscalationStep::Clear => "Clearing the conversation",
+            EscalationStep::Restart => "Restarting Claude",
+            EscalationStep::Fail => "Marking the task as failed",
+        }
+    }
+}
+
+/// One source of setting values, e.g. a config file, a profile or the command line.
+#[derive(Debug, Clone)]
+pub struct Layer {
+    /// Where the values came from, shown by `claudia config show`
+    pub source: String,
+    pub values: toml::Table,
+}
+
+impl Layer {
+    pub fn new(source: impl Into<String>) -> Self {
+        Self { source: source.into(), values: toml::Table::new() }
+    }
+
+    /// Set a single value from its textual form, e.g. `idle_timeout=20m`.
+    /// Values that are valid TOML (numbers, quoted strings, arrays) keep their
+    /// type; anything else is taken as a plain string.
+    pub fn set(&mut self, key: &str, value: &str) {
+        let parsed = toml::from_str::<toml::Table>(&format!("v = {}", value))
+            .ok()
+            .and_then(|mut t| t.remove("v"))
+            .unwrap_or_else(|| toml::Value::String(value.to_string()));
+        self.values.insert(key.to_string(), parsed);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+/// Effective settings together with the source of each value.
+#[derive(Debug, Clone)]
+pub struct Resolved {
+    pub settings: Settings,
+    pub values: toml::Table,
+    pub sources: std::collections::BTreeMap<String, String>,
+}
+
+impl Settings {
+    /// Merge `layers` (lowest precedence first) on top of the defaults.
+    pub fn resolve(layers: &[Layer]) -> Result<Resolved, ConfigError> {
+        let mut values = toml::Table::try_from(Settings::default())
+            .expect("default settings serialize to a table");
+        let mut sources: std::collections::BTreeMap<String, String> = values
+            .keys()
+            .map(|k| (k.clone(), "default".to_string()))
+            .collect();
+
+        for layer in layers {
+            for (key, value) in &layer.values {
+                if !values.contains_key(key) {
+                    return Err(ConfigError(format!("Unknown setting '{}' in {}", key, layer.source)));
+                }
+                merge(&mut values, key, value);
+                sources.insert(key.clone(), layer.source.clone());
+            }
+            // Type-check this layer so errors name the file they came from
+            Self::from_table(values.clone())
+                .map_err(|e| ConfigError(format!("Invalid setting in {}: {}", layer.source, e)))?;
+        }
+
+        let settings = Self::from_table(values.clone())
+            .map_err(|e| ConfigError(format!("Invalid settings: {}", e)))?;
+        settings.validate()?;
+        Ok(Resolved { settings, values, sources })
+    }
+
+    fn from_table(values: toml::Table) -> Result<Self, toml::de::Error> {
+        Settings::deserialize(toml::Value::Table(values))
+    }
+
+    /// Return a copy with an override layer (e.g. from a task annotation) applied.
+    pub fn with_override(&self, layer: &Layer) -> Result<Self, ConfigError> {
+        let mut values = toml::Table::try_from(self).expect("settings serialize to a table");
+        for (key, value) in &layer.values {
+            if !values.contains_key(key) {
+                return Err(ConfigError(format!("Unknown setting '{}' in {}", key, layer.source)));
+            }
+            merge(&mut values, key, value);
+        }
+        let settings = Self::from_table(values)
+            .map_err(|e| ConfigError(format!("Invalid setting in {}: {}", layer.source, e)))?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Arguments for the agent command, with the model added if one is set.
+    pub fn agent_command_args(&self) -> Vec<String> {
+        let mut args = self.agent_args.clone();
+        if !self.model.is_empty() {
+            args.extend(["--model".to_string(), self.model.clone()]);
+        }
+        args
+    }
+
+    /// Directory the agent runs in for the task file `md_file`.
+    pub fn working_dir(&self, md_file: &Path) -> PathBuf {
+        match self.working_dir.as_str() {
+            "" => parent_dir(md_file).to_path_buf(),
+            working_dir => relative_to(md_file, working_dir),
+        }
+    }
+
+    /// The prompt template file for the task file `md_file`, if one is set.
+    pub fn prompt_template_path(&self, md_file: &Path) -> Option<PathBuf> {
+        (!self.prompt_template_file.is_empty()).then(|| relative_to(md_file, &self.prompt_template_file))
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.agent_command.trim().is_empty() {
+            return Err(ConfigError("agent_command must not be empty".to_string()));
+        }
+        if self.idle_timeout < Duration::from_secs(1) {
+            return Err(ConfigError("idle_timeout must be at least 1s".to_string()));
+        }
+        if self.busy_cpu_percent == 0 {
+            return Err(ConfigError("busy_cpu_percent must be at least 1".to_string()));
+        }
+        if self.max_continues == 0 {
+            return Err(ConfigError("max_continues must be at least 1".to_string()));
+        }
+        if self.loop_window < 2 {
+            return Err(ConfigError("loop_window must be at least 2".to_string()));
+        }
+        if self.loop_compare_chars < 10 {
+            return Err(ConfigError("loop_compare_chars must be at least 10".to_string()));
+        }
+        if !(self.loop_similarity > 0.0 && self.loop_similarity <= 1.0) {
+            return Err(ConfigError("loop_similarity must be above 0 and at most 1".to_string()));
+        }
+        // The running indicator is looked for in the last 200 characters
+        if self.output_buffer_chars < self.loop_compare_chars.max(200) {
+            return Err(ConfigError(format!(
+                "output_buffer_chars must be at least {} (loop_compare_chars and the 200 characters used to detect a running Claude)",
+                self.loop_compare_chars.max(200)
+            )));
+        }
+        if self.continue_message.trim().is_empty() {
+            return Err(ConfigError("continue_message must not be empty".to_string()));
+        }
+        if self.wrap_up_message.trim().is_empty() {
+            return Err(ConfigError("wrap_up_message must not be empty".to_string()));
+        }
+        Template::parse("continue_message", &self.continue_message)?;
+        Template::parse("prompt_template", &self.prompt_template)?;
+        Template::parse("task_prompt", &self.task_prompt)?;
+        Template::parse("firm_message", &self.firm_message)?;
+        Template::parse("scoped_prompt", &self.scoped_prompt)?;
+        Template::parse("failed_message", &self.failed_message)?;
+        if self.escalation.is_empty() {
+            return Err(ConfigError("escalation must have at least one step".to_string()));
+        }
+        for webhook in &self.webhooks {
+            webhook.validate()?;
+        }
+        self.email.validate()?;
+        Template::parse("challenge_prompt", &self.challenge_prompt)?;
+        if self.suspicious_batch < 2 {
+            return Err(ConfigError("suspicious_batch must be at least 2".to_string()));
+        }
+        if self.suspicious_policy == SuspiciousPolicy::Verify && self.verify_command.is_empty() {
+            return Err(ConfigError("suspicious_policy = \"verify\" needs a verify_command".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Set `key` to `value`. A table (such as `hooks`) is merged into the one it
+/// overrides, so a layer can set some of its keys and keep the others.
+fn merge(values: &mut toml::Table, key: &str, value: &toml::Value) {
+    match (values.get_mut(key), value) {
+        (Some(toml::Value::Table(current)), toml::Value::Table(table)) => {
+            current.extend(table.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        _ => {
+            values.insert(key.to_string(), value.clone());
+        }
+    }
+}
+
+/// `path` taken relative to the directory of the task file `md_file`.
+fn relative_to(md_file: &Path, path: &str) -> PathBuf {
+    match parent_dir(md_file) {
+        dir if dir == Path::new(".") => PathBuf::from(path),
+        dir => dir.join(path),
+    }
+}
+
+/// Parse a duration such as `90`, `90s`, `10m`, `1h30m` or `2h`.
+/// A bare number is taken as seconds.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    if s.is_empty() {
+        anyhow::bail!("empty duration");
+    }
+    if let Ok(secs) = s.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+
+    let mut total = 0u64;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let value: u64 = number.parse().with_context(|| format!("invalid duration '{}'", s))?;
+        number.clear();
+        let unit: u64 = match c {
+            'd' => 86400,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => anyhow::bail!("invalid duration unit '{}' in '{}'", c, s),
+        };
+        total = value
+            .checked_mul(unit)
+            .and_then(|secs| total.checked_add(secs))
+            .with_context(|| format!("duration '{}' is too long", s))?;
+    }
+    if !number.is_empty() {
+        anyhow::bail!("missing unit after '{}' in '{}'", number, s);
+    }
+    Ok(Duration::from_secs(total))
+}
+
+/// Format a duration in the form accepted by `parse_duration`.
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    let (h, m, s) = (secs / 3600, (secs % 3600) / 60, secs % 60);
+    let mut out = String::new();
+    if h > 0 {
+        out.push_str(&format!("{}h", h));
+    }
+    if m > 0 {
+        out.push_str(&format!("{}m", m));
+    }
+    if s > 0 || out.is_empty() {
+        out.push_str(&format!("{}s", s));
+    }
+    out
+}
+
+/// Serde helpers storing a `Duration` as a string like `10m`; a plain
+/// number of seconds is accepted when reading.
+pub mod duration_format {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(d: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_duration(*d))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Secs(u64),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Secs(secs) => Ok(Duration::from_secs(secs)),
+            Raw::Text(text) => parse_duration(&text).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(parse_duration(" 2d ").unwrap(), Duration::from_secs(172_800));
+    }
+
+    #[test]
+    fn rejects_bad_durations() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("10x").is_err());
+        assert!(parse_duration("10m5").is_err());
+        assert!(parse_duration("h").is_err());
+    }
+
+    #[test]
+    fn rejects_durations_that_overflow() {
+        let error = parse_duration(&format!("{}d", u64::MAX / 86400 + 1)).unwrap_err();
+        assert!(error.to_string().contains("too long"));
+        assert!(parse_duration(&format!("{}s1s", u64::MAX)).is_err());
+    }
+}
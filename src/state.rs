@@ -0,0 +1,356 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::git;
+use crate::notify::Notice;
+use crate::schedule::WaitReason;
+use crate::settings::EscalationStep;
+use crate::ExitReason;
+
+/// Root of claudia's persistent state: `$CLAUDIA_STATE_DIR`,
+/// `$XDG_STATE_HOME/claudia` or `~/.local/state/claudia`.
+pub fn state_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("CLAUDIA_STATE_DIR").filter(|d| !d.is_empty()) {
+        return PathBuf::from(dir);
+    }
+    if let Some(dir) = std::env::var_os("XDG_STATE_HOME").filter(|d| !d.is_empty()) {
+        return PathBuf::from(dir).join("claudia");
+    }
+    match std::env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join(".local").join("state").join("claudia"),
+        None => PathBuf::from(".claudia"),
+    }
+}
+
+/// Directory holding one subdirectory per run.
+pub fn runs_dir() -> PathBuf {
+    state_dir().join("runs")
+}
+
+/// Remember that the usage limit is in effect until `until`, so that other
+/// runs (e.g. the next job of a daemon) wait instead of hitting it again.
+pub fn record_usage_limit(until: DateTime<Local>) {
+    let dir = state_dir();
+    let result = fs::create_dir_all(&dir)
+        .and_then(|_| fs::write(dir.join("usage_limit"), until.to_rfc3339()));
+    if let Err(e) = result {
+        eprintln!("[WARN] Failed to record usage limit in {}: {}", dir.display(), e);
+    }
+}
+
+/// The most recently recorded usage limit reset time, if it is still in the future.
+pub fn active_usage_limit() -> Option<DateTime<Local>> {
+    let content = fs::read_to_string(state_dir().join("usage_limit")).ok()?;
+    let until = DateTime::parse_from_rfc3339(content.trim()).ok()?.with_timezone(&Local);
+    (until > Local::now()).then_some(until)
+}
+
+/// Coarse lifecycle phase of a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Running,
+    Waiting,
+    Finished,
+}
+
+/// Snapshot of a run, rewritten to `state.json` whenever it changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunState {
+    pub run_id: String,
+    pub pid: u32,
+    pub md_file: PathBuf,
+    pub working_dir: PathBuf,
+    pub started_at: DateTime<Local>,
+    pub updated_at: DateTime<Local>,
+    pub phase: Phase,
+    pub status: String,
+    pub continues: u32,
+    pub tasks_total: usize,
+    pub tasks_completed: usize,
+    #[serde(default)]
+    pub waiting_until: Option<DateTime<Local>>,
+    #[serde(default)]
+    pub wait_reason: Option<WaitReason>,
+    #[serde(default)]
+    pub reason: Option<ExitReason>,
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    /// Set while Continues are suspended through the control socket
+    #[serde(default)]
+    pub paused: bool,
+    /// Commit checked out in the working directory when the run started
+    #[serde(default)]
+    pub start_commit: Option<String>,
+}
+
+impl RunState {
+    /// Whether the state says the run is active but its process is gone.
+    pub fn is_stale(&self) -> bool {
+        self.phase != Phase::Finished && !is_process_alive(self.pid)
+    }
+}
+
+/// Something that happened during a run, appended to `events.jsonl`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub time: DateTime<Local>,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EventKind {
+    RunStarted { md_file: PathBuf, tasks_total: usize, tasks_completed: usize },
+    Status { message: String },
+    ContinueSent { count: u32 },
+    UsageLimit { until: DateTime<Local> },
+    Resumed,
+    WindowClosed { until: DateTime<Local> },
+    WindowOpened,
+    WrapUpRequested { deadline: DateTime<Local> },
+    TaskCompleted { text: String },
+    TasksAdded { tasks: Vec<String> },
+    Escalated { step: EscalationStep, task: String },
+    TaskFailed { text: String },
+    VerifyPassed { command: String },
+    VerifyFailed { command: String, status: String },
+    AgentExited { status: String },
+    AgentRestarted { attempt: u32, delay_secs: u64 },
+    WebhookFailed { url: String, notice: Notice, error: String },
+    HookFailed { hook: String, error: String },
+    TasksRestored { changes: Vec<String> },
+    SuspiciousCompletion { text: String, reasons: Vec<String> },
+    Stalled { continues: u32, files_watched: bool },
+    ControlCommand { command: String },
+    MessageInjected { message: String },
+    RunFinished { reason: ExitReason, exit_code: i32, tasks_total: usize, tasks_completed: usize, continues: u32 },
+}
+
+/// Persistent record of the current run: its state file and event log.
+/// Write failures are reported once and otherwise ignored so that a full
+/// disk never stops Claude from working.
+pub struct RunRecord {
+    dir: PathBuf,
+    state: Mutex<RunState>,
+    write_failed: Mutex<bool>,
+}
+
+impl RunRecord {
+    pub fn create(md_file: &Path, working_dir: &Path) -> Result<Self> {
+        Self::create_in(&runs_dir(), md_file, working_dir)
+    }
+
+    /// Like `create`, with the run directory under `runs_dir`.
+    pub fn create_in(runs_dir: &Path, md_file: &Path, working_dir: &Path) -> Result<Self> {
+        let now = Local::now();
+        let pid = std::process::id();
+        fs::create_dir_all(runs_dir)
+            .with_context(|| format!("Failed to create run directory {}", runs_dir.display()))?;
+        // Several task files can be run by one process within the same second
+        let base_id = format!("{}-{}", now.format("%Y%m%d-%H%M%S"), pid);
+        let mut run_id = base_id.clone();
+        let mut n = 1;
+        let dir = loop {
+            let dir = runs_dir.join(&run_id);
+            match fs::create_dir(&dir) {
+                Ok(()) => break dir,
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    n += 1;
+                    run_id = format!("{}-{}", base_id, n);
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to create run directory {}", dir.display()));
+                }
+            }
+        };
+
+        let absolute = |p: &Path| p.canonicalize().unwrap_or_else(|_| p.to_path_buf());
+        let record = Self {
+            dir,
+            state: Mutex::new(RunState {
+                run_id,
+                pid,
+                md_file: absolute(md_file),
+                working_dir: absolute(working_dir),
+                started_at: now,
+                updated_at: now,
+                phase: Phase::Running,
+                status: "Starting...".to_string(),
+                continues: 0,
+                tasks_total: 0,
+                tasks_completed: 0,
+                waiting_until: None,
+                wait_reason: None,
+                reason: None,
+                exit_code: None,
+                paused: false,
+                start_commit: git::head_commit(working_dir),
+            }),
+            write_failed: Mutex::new(false),
+        };
+        record.save();
+        Ok(record)
+    }
+
+    pub fn run_id(&self) -> String {
+        self.state.lock().unwrap().run_id.clone()
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn state(&self) -> RunState {
+        self.state.lock().unwrap().clone()
+    }
+
+    /// Modify the state and persist it.
+    pub fn update(&self, f: impl FnOnce(&mut RunState)) {
+        {
+            let mut state = self.state.lock().unwrap();
+            f(&mut state);
+            state.updated_at = Local::now();
+        }
+        self.save();
+    }
+
+    pub fn event(&self, kind: EventKind) {
+        let event = Event { time: Local::now(), kind };
+        let result = serde_json::to_string(&event)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| {
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(self.dir.join("events.jsonl"))?;
+                writeln!(file, "{}", line)?;
+                Ok(())
+            });
+        self.report_failure(result);
+    }
+
+    fn save(&self) {
+        let state = self.state.lock().unwrap().clone();
+        let result = serde_json::to_string_pretty(&state)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| {
+                // Write to a temporary file and rename so readers never see a partial file
+                let tmp = self.dir.join("state.json.tmp");
+                fs::write(&tmp, json)?;
+                fs::rename(&tmp, self.dir.join("state.json"))?;
+                Ok(())
+            });
+        self.report_failure(result);
+    }
+
+    fn report_failure(&self, result: Result<()>) {
+        if let Err(e) = result {
+            let mut failed = self.write_failed.lock().unwrap();
+            if !*failed {
+                eprintln!("[WARN] Failed to write run state in {}: {}", self.dir.display(), e);
+                *failed = true;
+            }
+        }
+    }
+}
+
+pub fn load_state(run_dir: &Path) -> Result<RunState> {
+    let path = run_dir.join("state.json");
+    let content = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&content).with_context(|| format!("Invalid state file {}", path.display()))
+}
+
+pub fn load_events(run_dir: &Path) -> Result<Vec<Event>> {
+    let path = run_dir.join("events.jsonl");
+    let content = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    // Skip lines that don't parse, e.g. a partial line from a crashed run
+    Ok(content.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+}
+
+/// All recorded runs, oldest first.
+pub fn list_runs() -> Result<Vec<RunState>> {
+    let dir = runs_dir();
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut runs: Vec<RunState> = fs::read_dir(&dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| load_state(&entry.path()).ok())
+        .collect();
+    runs.sort_by_key(|run| run.started_at);
+    Ok(runs)
+}
+
+/// Directory of the run matching `query` (a run ID or unique prefix of one),
+/// or of the most recent run when no query is given.
+pub fn find_run(query: Option<&str>) -> Result<PathBuf> {
+    let runs = list_runs()?;
+    let run = match query {
+        None => runs.last(),
+        Some(query) => {
+            // An exact ID wins over longer IDs it is a prefix of (e.g. `<id>-2`)
+            let exact: Vec<_> = runs.iter().filter(|r| r.run_id == query).collect();
+            let matches = if exact.is_empty() {
+                runs.iter().filter(|r| r.run_id.starts_with(query)).collect()
+            } else {
+                exact
+            };
+            if matches.len() > 1 {
+                anyhow::bail!("Run ID '{}' is ambiguous ({} runs match)", query, matches.len());
+            }
+            matches.first().copied()
+        }
+    };
+    match run {
+        Some(run) => Ok(runs_dir().join(&run.run_id)),
+        None => match query {
+            Some(query) => anyhow::bail!("No run matching '{}' in {}", query, runs_dir().display()),
+            None => anyhow::bail!("No runs recorded in {}", runs_dir().display()),
+        },
+    }
+}
+
+/// Whether a process with this PID exists.
+pub fn is_process_alive(pid: u32) -> bool {
+    // 0 and negative PIDs would address process groups
+    let Ok(pid) = i32::try_from(pid) else {
+        return false;
+    };
+    if pid == 0 {
+        return false;
+    }
+    // SAFETY: signal 0 only checks that the process exists and may be signalled
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    // It exists but belongs to another user
+    std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tells_live_processes_from_gone_ones() {
+        assert!(is_process_alive(std::process::id()));
+        // init runs as root, so this also covers a process we may not signal
+        assert!(is_process_alive(1));
+        assert!(!is_process_alive(0));
+        assert!(!is_process_alive(u32::MAX));
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        assert!(!is_process_alive(pid));
+    }
+}
@@ -0,0 +1,528 @@
+use regex::Regex;
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+use crate::settings::{Layer, Settings};
+use crate::ConfigError;
+
+/// Settings a task annotation may override. The others (continue cap, agent
+/// command, buffer size) apply to the whole run.
+const TASK_OVERRIDABLE: &[&str] = &["idle_timeout", "loop_window", "loop_compare_chars", "loop_similarity"];
+
+/// A checkbox item in the task file.
+#[derive(Debug, Clone)]
+pub struct Task {
+    /// One-based position among the tasks in the file
+    pub id: usize,
+    /// Zero-based line index in the file
+    pub line: usize,
+    pub checked: bool,
+    /// Task text after the checkbox
+    pub text: String,
+}
+
+impl Task {
+    /// Settings overridden for this task with an annotation such as
+    /// `<!-- claudia: idle_timeout=20m loop_window=5 -->`.
+    pub fn overrides(&self) -> Result<Layer, ConfigError> {
+        let mut overrides = Layer::new(format!("task on line {}", self.line + 1));
+        static ANNOTATION: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<!--\s*claudia:(.*?)-->").unwrap());
+        if let Some(captures) = ANNOTATION.captures(&self.text) {
+            for pair in captures[1].split_whitespace() {
+                let (key, value) = pair.split_once('=').ok_or_else(|| {
+                    ConfigError(format!("Invalid task override '{}' on line {}", pair, self.line + 1))
+                })?;
+                if !TASK_OVERRIDABLE.contains(&key) {
+                    return Err(ConfigError(format!(
+                        "'{}' cannot be overridden per task (line {})", key, self.line + 1
+                    )));
+                }
+                overrides.set(key, value);
+            }
+        }
+        Ok(overrides)
+    }
+
+    /// Target of a link to another Markdown file in the task text, e.g.
+    /// `[plan](api/plan.md)`, relative to the task file. Such a task is a
+    /// sub-plan and is done once every task in the linked file is done.
+    pub fn link(&self) -> Option<String> {
+        static LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\]\(([^)\s]+\.md)\)").unwrap());
+        let target = LINK.captures(&self.text)?[1].to_string();
+        (!target.contains("://")).then_some(target)
+    }
+}
+
+/// Files pulled into the plan with `<!-- include: backend.md -->`, in order.
+pub fn includes(content: &str) -> Vec<String> {
+    static INCLUDE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<!--\s*include:\s*(.+?)\s*-->").unwrap());
+    INCLUDE.captures_iter(content).map(|c| c[1].to_string()).collect()
+}
+
+/// Number of lines taken by a YAML front matter block (`---` ... `---`) at
+/// the top of the file, or 0 if there is none.
+pub fn front_matter_lines(content: &str) -> usize {
+    let mut lines = content.lines();
+    if lines.next().map(str::trim_end) != Some("---") {
+        return 0;
+    }
+    lines.position(|line| matches!(line.trim_end(), "---" | "...")).map_or(0, |end| end + 2)
+}
+
+/// The YAML text of the front matter block, if the file starts with one.
+pub fn front_matter(content: &str) -> Option<String> {
+    let lines = front_matter_lines(content);
+    (lines > 0).then(|| content.lines().skip(1).take(lines - 2).collect::<Vec<_>>().join("\n"))
+}
+
+/// Parse all checkbox items (`- [ ]`, `* [x]`, `+ [X]`, `1. [ ]`) from the file content.
+pub fn parse_tasks(content: &str) -> Vec<Task> {
+    static CHECKBOX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^\s*(?:[-*+]|\d+\.)\s+\[([ xX])\]\s?(.*)$").unwrap());
+    content
+        .lines()
+        .enumerate()
+        .skip(front_matter_lines(content))
+        .filter_map(|(line, text)| {
+            let captures = CHECKBOX.captures(text)?;
+            Some((line, &captures[1] != " ", captures[2].to_string()))
+        })
+        .enumerate()
+        .map(|(i, (line, checked, text))| Task { id: i + 1, line, checked, text })
+        .collect()
+}
+
+/// Checked and total number of tasks, not counting brackets in front matter or prose.
+pub fn counts(content: &str) -> (usize, usize) {
+    let tasks = parse_tasks(content);
+    (tasks.iter().filter(|t| t.checked).count(), tasks.len())
+}
+
+/// Add `[ ]` checkboxes to list items that don't have one. Numbered items
+/// become `- [ ]` items. Front matter is left alone. Returns `None` if
+/// nothing needed to change.
+pub fn normalize_checkboxes(content: &str) -> Option<String> {
+    let mut modified = false;
+    let mut new_content = String::new();
+    let front_matter = front_matter_lines(content);
+    
+    // Process each line
+    for (i, line) in content.lines().enumerate() {
+        let trimmed = line.trim_start();
+        
+        // Front matter holds run settings, not tasks
+        if i < front_matter {
+            new_content.push_str(line);
+        // Check if this is a list item without a checkbox
+        } else if trimmed.starts_with("- ") || trimmed.starts_with("* ") || 
+            trimmed.starts_with("+ ") || trimmed.starts_with(char::is_numeric) {
+            
+            // Check if it already has a checkbox
+            if !trimmed.contains("- [ ]") && !trimmed.contains("- [x]") && 
+               !trimmed.contains("- [X]") && !trimmed.contains("* [ ]") && 
+               !trimmed.contains("* [x]") && !trimmed.contains("* [X]") {
+                
+                // Add checkbox after the list marker
+                if trimmed.starts_with("- ") {
+                    new_content.push_str(&line.replace("- ", "- [ ] "));
+                    modified = true;
+                } else if trimmed.starts_with("* ") {
+                    new_content.push_str(&line.replace("* ", "* [ ] "));
+                    modified = true;
+                } else if trimmed.starts_with("+ ") {
+                    new_content.push_str(&line.replace("+ ", "+ [ ] "));
+                    modified = true;
+                } else if let Some(pos) = trimmed.find(". ") {
+                    // Numbered list
+                    let (_num, rest) = trimmed.split_at(pos + 2);
+                    new_content.push_str(&format!("{}- [ ] {}", 
+                        " ".repeat(line.len() - trimmed.len()), rest));
+                    modified = true;
+                } else {
+                    new_content.push_str(line);
+                }
+            } else {
+                new_content.push_str(line);
+            }
+        } else {
+            new_content.push_str(line);
+        }
+        new_content.push('\n');
+    }
+    
+    // Remove the last newline if the original didn't have one
+    if !content.ends_with('\n') && new_content.ends_with('\n') {
+        new_content.pop();
+    }
+    
+    if modified {
+        Some(new_content)
+    } else {
+        None
+    }
+}
+
+/// A list item that `normalize_checkboxes` gives a checkbox.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckboxChange {
+    /// Zero-based line of the item
+    pub line: usize,
+    pub before: String,
+    pub after: String,
+}
+
+/// The list items of `content` that get a checkbox when the run starts, in order.
+pub fn checkbox_changes(content: &str) -> Vec<CheckboxChange> {
+    let Some(normalized) = normalize_checkboxes(content) else {
+        return Vec::new();
+    };
+    let before: Vec<&str> = content.lines().collect();
+    let after: Vec<&str> = normalized.lines().collect();
+    parse_tasks(&normalized)
+        .into_iter()
+        .filter_map(|task| {
+            let (before, after) = (*before.get(task.line)?, *after.get(task.line)?);
+            (before != after).then(|| CheckboxChange {
+                line: task.line,
+                before: before.to_string(),
+                after: after.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Like `normalize_checkboxes`, but only for lines that aren't in `previous`,
+/// so list items that were left alone (or written by Claude) stay as they are.
+pub fn normalize_new_items(previous: &str, content: &str) -> Option<String> {
+    let normalized = normalize_checkboxes(content)?;
+    let previous: HashSet<&str> = previous.lines().collect();
+    let mut modified = false;
+    let mut new_content = String::new();
+    // normalize_checkboxes keeps one output line per input line
+    for (line, fixed) in content.lines().zip(normalized.lines()) {
+        if line != fixed && !previous.contains(line) {
+            new_content.push_str(fixed);
+            modified = true;
+        } else {
+            new_content.push_str(line);
+        }
+        new_content.push('\n');
+    }
+    if !content.ends_with('\n') && new_content.ends_with('\n') {
+        new_content.pop();
+    }
+    modified.then_some(new_content)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A problem found by `lint`.
+#[derive(Debug, Clone)]
+pub struct Issue {
+    /// One-based line number, if the issue concerns a specific line
+    pub line: Option<usize>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Check a task file for problems that would make a run misbehave.
+pub fn lint(content: &str, settings: &Settings) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut issue = |line: Option<usize>, severity, message: String| {
+        issues.push(Issue { line, severity, message });
+    };
+
+    static MALFORMED: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^\s*(?:[-*+]|\d+\.)\s+\[([^\]]{0,3})\]").unwrap());
+    for (i, line) in content.lines().enumerate().skip(front_matter_lines(content)) {
+        if let Some(captures) = MALFORMED.captures(line) {
+            if !matches!(&captures[1], " " | "x" | "X") {
+                issue(Some(i + 1), Severity::Error,
+                    format!("Malformed checkbox '[{}]'; use '[ ]' or '[x]'", &captures[1]));
+            }
+        }
+    }
+
+    if let Some(normalized) = normalize_checkboxes(content) {
+        for (i, (before, after)) in content.lines().zip(normalized.lines()).enumerate() {
+            if before != after {
+                issue(Some(i + 1), Severity::Warning,
+                    "List item has no checkbox; '[ ]' will be added when the run starts".to_string());
+            }
+        }
+    }
+
+    let tasks = parse_tasks(&normalize_checkboxes(content).unwrap_or_else(|| content.to_string()));
+    if tasks.is_empty() && includes(content).is_empty() {
+        issue(None, Severity::Error, "No tasks found".to_string());
+    }
+
+    let mut seen = std::collections::HashMap::new();
+    for task in &tasks {
+        let text = task.text.trim();
+        if text.is_empty() {
+            issue(Some(task.line + 1), Severity::Warning, "Task has no text".to_string());
+        } else if let Some(first) = seen.insert(text.to_string(), task.line + 1) {
+            issue(Some(task.line + 1), Severity::Warning,
+                format!("Duplicate of the task on line {}", first));
+        }
+        if let Err(e) = task.overrides().and_then(|o| settings.with_override(&o)) {
+            issue(Some(task.line + 1), Severity::Error, e.to_string());
+        }
+    }
+
+    issues.sort_by_key(|i| i.line);
+    issues
+}
+
+/// An edit that changed existing tasks beyond their checkboxes, see `guard`.
+#[derive(Debug, Clone)]
+pub struct Tampering {
+    /// The edit with the existing tasks put back as they were, keeping its
+    /// checkbox states, added tasks and other lines
+    pub restored: String,
+    /// What the edit changed, e.g. `"Add tests" was removed`
+    pub changes: Vec<String>,
+}
+
+/// Check that an edit from `previous` to `content` left the existing tasks
+/// alone apart from their checkboxes. Tasks may be added, and text appended
+/// to a task (e.g. a note on how it was done) is allowed as an annotation.
+/// Returns how to undo anything else.
+pub fn guard(previous: &str, content: &str) -> Option<Tampering> {
+    let old = parse_tasks(previous);
+    let new = parse_tasks(content);
+    let mut used = vec![false; new.len()];
+    let mut find = |matches: &dyn Fn(&Task) -> bool| {
+        let j = new.iter().enumerate().position(|(j, t)| !used[j] && matches(t))?;
+        used[j] = true;
+        Some(j)
+    };
+    let matched: Vec<Option<usize>> = old
+        .iter()
+        .map(|task| {
+            let text = task.text.trim_end();
+            find(&|t| t.text.trim_end() == text)
+                .or_else(|| find(&|t| !text.is_empty() && t.text.starts_with(text)))
+        })
+        .collect();
+
+    // A new task between the same neighbours as a vanished one took its
+    // place (it was reworded); other new tasks were added
+    let mut replaced: Vec<Option<usize>> = vec![None; old.len()];
+    for i in (0..old.len()).filter(|i| matched[*i].is_none()) {
+        let after = matched[..i].iter().rev().flatten().next().copied();
+        // After a reorder the neighbours may be the other way round
+        let before = matched[i + 1..].iter().flatten().next().copied().filter(|b| after.is_none_or(|a| a < *b));
+        let candidate = (0..new.len())
+            .find(|&j| !used[j] && after.is_none_or(|a| j > a) && before.is_none_or(|b| j < b));
+        if let Some(j) = candidate {
+            used[j] = true;
+            replaced[i] = Some(j);
+        }
+    }
+
+    let mapped: Vec<(usize, usize)> =
+        (0..old.len()).filter_map(|i| matched[i].or(replaced[i]).map(|j| (i, j))).collect();
+    let reordered = mapped.windows(2).any(|pair| pair[0].1 > pair[1].1);
+    if !reordered && mapped.len() == old.len() && replaced.iter().all(Option::is_none) {
+        return None;
+    }
+
+    // Put the existing tasks back in their order on the lines the edit left
+    // them on; everything else stays as edited
+    let previous_lines: Vec<&str> = previous.lines().collect();
+    let edited: Vec<&str> = content.lines().collect();
+    let mut lines: Vec<String> = edited.iter().map(|line| line.to_string()).collect();
+    let mut slots: Vec<usize> = mapped.iter().map(|&(_, j)| new[j].line).collect();
+    slots.sort_unstable();
+    let mut slot_of: Vec<Option<usize>> = vec![None; old.len()];
+    let mut changes = Vec::new();
+    for (&(i, j), &slot) in mapped.iter().zip(&slots) {
+        lines[slot] = if matched[i].is_some() {
+            // Unchanged or annotated: keep the edited line
+            edited[new[j].line].to_string()
+        } else {
+            changes.push(format!("\"{}\" was changed to \"{}\"", old[i].text, new[j].text));
+            set_checkbox(previous_lines[old[i].line], new[j].checked)
+        };
+        slot_of[i] = Some(slot);
+    }
+    if reordered {
+        changes.push("the tasks were reordered".to_string());
+    }
+
+    // Removed tasks go back after the task they used to follow
+    let mut inserts: Vec<(usize, String)> = Vec::new();
+    for i in (0..old.len()).filter(|i| slot_of[*i].is_none()) {
+        changes.push(format!("\"{}\" was removed", old[i].text));
+        let at = slot_of[..i].iter().rev().flatten().next().map(|slot| slot + 1)
+            .or_else(|| slot_of[i + 1..].iter().flatten().next().copied())
+            .unwrap_or(old[i].line.min(lines.len()));
+        inserts.push((at, previous_lines[old[i].line].to_string()));
+    }
+    let mut restored_lines = Vec::new();
+    for position in 0..=lines.len() {
+        restored_lines.extend(inserts.iter().filter(|(at, _)| *at == position).map(|(_, line)| line.clone()));
+        restored_lines.extend(lines.get(position).cloned());
+    }
+    let mut restored = restored_lines.join("\n");
+    if content.ends_with('\n') {
+        restored.push('\n');
+    }
+    Some(Tampering { restored, changes })
+}
+
+/// `line` with its checkbox set to `[x]` or `[ ]`.
+fn set_checkbox(line: &str, checked: bool) -> String {
+    static CHECKBOX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(\s*(?:[-*+]|\d+\.)\s+\[)[ xX]\]").unwrap());
+    CHECKBOX.replace(line, format!("${{1}}{}]", if checked { "x" } else { " " })).into_owned()
+}
+
+/// `content` with the checkbox on line `line` (zero-based) set to `[x]`.
+pub fn check(content: &str, line: usize) -> String {
+    set_line_checkbox(content, line, true)
+}
+
+/// `content` with the checkbox on line `line` (zero-based) set back to `[ ]`.
+pub fn reopen(content: &str, line: usize) -> String {
+    set_line_checkbox(content, line, false)
+}
+
+fn set_line_checkbox(content: &str, line: usize, checked: bool) -> String {
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    if let Some(text) = lines.get_mut(line) {
+        *text = set_checkbox(text, checked);
+    }
+    let mut updated = lines.join("\n");
+    if content.ends_with('\n') {
+        updated.push('\n');
+    }
+    updated
+}
+
+/// Title of the heading the line `line` falls under, if any.
+pub fn section(content: &str, line: usize) -> Option<String> {
+    content
+        .lines()
+        .enumerate()
+        .skip(front_matter_lines(content))
+        .take_while(|(i, _)| *i < line)
+        .filter(|(_, text)| text.starts_with('#'))
+        .last()
+        .map(|(_, text)| text.trim_start_matches('#').trim().to_string())
+}
+
+/// The first unchecked task, which Claude is expected to be working on.
+pub fn current_task(tasks: &[Task]) -> Option<&Task> {
+    tasks.iter().find(|t| !t.checked)
+}
+
+/// Check that every per-task override is valid and yields valid settings.
+pub fn validate_overrides(tasks: &[Task], base: &Settings) -> Result<(), ConfigError> {
+    for task in tasks {
+        let overrides = task.overrides()?;
+        if !overrides.is_empty() {
+            base.with_override(&overrides).map_err(|e| {
+                ConfigError(format!("{} (task on line {})", e, task.line + 1))
+            })?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_the_items_that_get_a_checkbox() {
+        let content = "---\ntags:\n  - nightly\n---\n# Plan\n- [x] Done\n- Open\n  1. Nested step\n* [ ] Already boxed\n";
+        let change = |line: usize, before: &str, after: &str| CheckboxChange {
+            line,
+            before: before.to_string(),
+            after: after.to_string(),
+        };
+        assert_eq!(checkbox_changes(content), [
+            change(6, "- Open", "- [ ] Open"),
+            change(7, "  1. Nested step", "  - [ ] Nested step"),
+        ]);
+        assert!(checkbox_changes("- [ ] A\n- [x] B\n").is_empty());
+    }
+
+    #[test]
+    fn counts_ignore_brackets_in_front_matter() {
+        let content = "---\nrules: [\"Only flip [ ] to [x]\"]\n---\n# Plan\n- [x] A\n- [X] B\n- [x] C\n";
+        assert_eq!(counts(content), (3, 3));
+    }
+
+    const PLAN: &str = "# Plan\n- [ ] Add tests\n- [ ] Fix the parser\n- [ ] Write docs\n";
+
+    #[test]
+    fn guard_allows_checkboxes_notes_and_added_tasks() {
+        let edit = "# Plan\nSome notes.\n- [x] Add tests (see abc123)\n- [ ] New task\n- [ ] Fix the parser\n- [ ] Write docs\n";
+        assert!(guard(PLAN, edit).is_none());
+    }
+
+    #[test]
+    fn guard_restores_reworded_task_with_its_checkbox() {
+        let edit = "# Plan\n- [ ] Add tests\n- [x] Parser fixed\n- [ ] Write docs\n";
+        let tampering = guard(PLAN, edit).unwrap();
+        assert_eq!(tampering.restored, "# Plan\n- [ ] Add tests\n- [x] Fix the parser\n- [ ] Write docs\n");
+        assert_eq!(tampering.changes, vec!["\"Fix the parser\" was changed to \"Parser fixed\"".to_string()]);
+    }
+
+    #[test]
+    fn guard_undoes_reorder() {
+        let edit = "# Plan\n- [ ] Write docs\n- [x] Add tests\n- [ ] Fix the parser\n";
+        let tampering = guard(PLAN, edit).unwrap();
+        assert_eq!(tampering.restored, "# Plan\n- [x] Add tests\n- [ ] Fix the parser\n- [ ] Write docs\n");
+        assert_eq!(tampering.changes, vec!["the tasks were reordered".to_string()]);
+    }
+
+    #[test]
+    fn guard_restores_reworded_task_among_reordered_ones() {
+        let edit = "# Plan\n- [ ] Write docs\n- [x] Add tests\n- [ ] Parser fixed\n";
+        let tampering = guard(PLAN, edit).unwrap();
+        assert_eq!(tampering.restored, "# Plan\n- [x] Add tests\n- [ ] Fix the parser\n- [ ] Write docs\n");
+        assert_eq!(tampering.changes, vec![
+            "\"Fix the parser\" was changed to \"Parser fixed\"".to_string(),
+            "the tasks were reordered".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn guard_puts_removed_task_back_in_place() {
+        let edit = "# Plan\n- [x] Add tests\n- [ ] Write docs\n";
+        let tampering = guard(PLAN, edit).unwrap();
+        assert_eq!(tampering.restored, "# Plan\n- [x] Add tests\n- [ ] Fix the parser\n- [ ] Write docs\n");
+        assert_eq!(tampering.changes, vec!["\"Fix the parser\" was removed".to_string()]);
+    }
+
+    #[test]
+    fn guard_keeps_other_lines_and_added_tasks_where_written() {
+        let edit = "# Plan\n- [x] Add tests\n- [ ] Added in the middle\n- [ ] Fix the parser\n\n## Notes\nParser is tricky.\n- [x] Docs written\n";
+        let tampering = guard(PLAN, edit).unwrap();
+        assert_eq!(
+            tampering.restored,
+            "# Plan\n- [x] Add tests\n- [ ] Added in the middle\n- [ ] Fix the parser\n\n## Notes\nParser is tricky.\n- [x] Write docs\n"
+        );
+    }
+
+    #[test]
+    fn guard_appended_task_is_not_a_rewording() {
+        let edit = "# Plan\n- [x] Add tests\n- [ ] Write docs\n- [ ] Brand new\n";
+        let tampering = guard(PLAN, edit).unwrap();
+        assert_eq!(tampering.restored, "# Plan\n- [x] Add tests\n- [ ] Fix the parser\n- [ ] Write docs\n- [ ] Brand new\n");
+    }
+
+    #[test]
+    fn counts_ignore_brackets_in_prose() {
+        let content = "Mark tasks [x] when done, leave [ ] otherwise.\n- [ ] A\n- [x] B\n";
+        assert_eq!(counts(content), (1, 2));
+    }
+}
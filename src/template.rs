@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+
+use crate::ConfigError;
+
+/// Variables a template can use.
+pub const VARIABLES: &[&str] = &[
+    "file_name",
+    "file_path",
+    "working_dir",
+    "run_id",
+    "tasks",
+    "remaining_tasks",
+    "next_tasks",
+    "total_count",
+    "completed_count",
+    "remaining_count",
+    "current_task",
+    "section",
+    "plan_files",
+    "rules",
+    "previous_failure",
+    "failed_task",
+    "event",
+    "summary",
+    "suspect_task",
+    "suspicion",
+];
+
+/// Values of the template variables, by name.
+pub type Vars = BTreeMap<&'static str, String>;
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Var(String),
+    /// `{{#if name}}...{{else}}...{{/if}}`, or `{{#unless name}}` with `negate`
+    If { name: String, negate: bool, then: Vec<Node>, otherwise: Vec<Node> },
+}
+
+enum Token {
+    Text(String),
+    Tag(String),
+}
+
+/// A prompt or message with `{{variable}}` placeholders and
+/// `{{#if variable}}...{{else}}...{{/if}}` blocks. A variable counts as set
+/// when it is not empty. A block tag on a line of its own takes the whole
+/// line, so templates can be laid out readably.
+#[derive(Debug, Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+fn tokenize(template: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = template;
+    let mut at_line_start = true;
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..].find("}}").ok_or("'{{' is not closed with '}}'")? + start;
+        let tag = rest[start + 2..end].trim().to_string();
+        let mut text = &rest[..start];
+        rest = &rest[end + 2..];
+
+        let is_block = tag.starts_with(['#', '/']) || tag == "else";
+        let line_start = text.rfind('\n').map_or(0, |i| i + 1);
+        let standalone = is_block
+            && text[line_start..].trim().is_empty()
+            && (line_start > 0 || at_line_start)
+            && (rest.is_empty() || rest.starts_with('\n'));
+        if standalone {
+            text = &text[..line_start];
+            rest = rest.strip_prefix('\n').unwrap_or(rest);
+        }
+        at_line_start = standalone;
+
+        if !text.is_empty() {
+            tokens.push(Token::Text(text.to_string()));
+        }
+        tokens.push(Token::Tag(tag));
+    }
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest.to_string()));
+    }
+    Ok(tokens)
+}
+
+fn variable(name: &str) -> Result<String, String> {
+    if VARIABLES.contains(&name) {
+        Ok(name.to_string())
+    } else {
+        Err(format!("unknown variable '{}' (available: {})", name, VARIABLES.join(", ")))
+    }
+}
+
+/// Parse nodes up to the end of the template or a closing tag, which is returned.
+fn parse_nodes(tokens: &mut impl Iterator<Item = Token>) -> Result<(Vec<Node>, Option<String>), String> {
+    let mut nodes = Vec::new();
+    while let Some(token) = tokens.next() {
+        let tag = match token {
+            Token::Text(text) => {
+                nodes.push(Node::Text(text));
+                continue;
+            }
+            Token::Tag(tag) => tag,
+        };
+        if tag == "else" || tag.starts_with('/') {
+            return Ok((nodes, Some(tag)));
+        }
+        let Some(block) = tag.strip_prefix('#') else {
+            nodes.push(Node::Var(variable(&tag)?));
+            continue;
+        };
+
+        let (keyword, name) = block.split_once(char::is_whitespace).unwrap_or((block, ""));
+        let negate = match keyword {
+            "if" => false,
+            "unless" => true,
+            _ => return Err(format!("unknown block '{{{{#{}}}}}' (use #if or #unless)", keyword)),
+        };
+        let name = variable(name.trim())?;
+        let (then, end) = parse_nodes(tokens)?;
+        let (otherwise, end) = if end.as_deref() == Some("else") { parse_nodes(tokens)? } else { (Vec::new(), end) };
+        if end.as_deref() != Some(&format!("/{}", keyword)) {
+            return Err(format!("'{{{{#{} {}}}}}' is not closed with '{{{{/{}}}}}'", keyword, name, keyword));
+        }
+        nodes.push(Node::If { name, negate, then, otherwise });
+    }
+    Ok((nodes, None))
+}
+
+fn render_nodes(nodes: &[Node], vars: &Vars, out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Var(name) => out.push_str(vars.get(name.as_str()).map_or("", String::as_str)),
+            Node::If { name, negate, then, otherwise } => {
+                let set = vars.get(name.as_str()).is_some_and(|v| !v.trim().is_empty());
+                render_nodes(if set != *negate { then } else { otherwise }, vars, out);
+            }
+        }
+    }
+}
+
+impl Template {
+    /// Parse `text`; `name` says which template an error is about.
+    pub fn parse(name: &str, text: &str) -> Result<Self, ConfigError> {
+        let error = |e: String| ConfigError(format!("Invalid template in {}: {}", name, e));
+        let (nodes, end) = parse_nodes(&mut tokenize(text).map_err(error)?.into_iter()).map_err(error)?;
+        if let Some(end) = end {
+            return Err(error(format!("unexpected '{{{{{}}}}}'", end)));
+        }
+        Ok(Self { nodes })
+    }
+
+    /// Fill in the template. Trailing whitespace is dropped so the result can
+    /// be typed into Claude's prompt as is.
+    pub fn render(&self, vars: &Vars) -> String {
+        let mut out = String::new();
+        render_nodes(&self.nodes, vars, &mut out);
+        out.truncate(out.trim_end().len());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(text: &str, vars: &[(&'static str, &str)]) -> String {
+        let vars: Vars = vars.iter().map(|(name, value)| (*name, value.to_string())).collect();
+        Template::parse("test", text).unwrap().render(&vars)
+    }
+
+    fn parse_error(text: &str) -> String {
+        Template::parse("test", text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn fills_in_variables() {
+        assert_eq!(render("Work on {{ file_name }}: {{current_task}}.", &[("file_name", "t.md")]), "Work on t.md: .");
+    }
+
+    #[test]
+    fn renders_if_and_else() {
+        let template = "{{#if current_task}}Now: {{current_task}}{{else}}All done{{/if}}";
+        assert_eq!(render(template, &[("current_task", "Write tests")]), "Now: Write tests");
+        assert_eq!(render(template, &[]), "All done");
+        // Blank counts as not set
+        assert_eq!(render(template, &[("current_task", "  ")]), "All done");
+    }
+
+    #[test]
+    fn renders_unless_and_else() {
+        let template = "{{#unless failed_task}}On track{{else}}Gave up on {{failed_task}}{{/unless}}";
+        assert_eq!(render(template, &[]), "On track");
+        assert_eq!(render(template, &[("failed_task", "Deploy")]), "Gave up on Deploy");
+        assert_eq!(render("{{#unless rules}}No rules{{/unless}}", &[("rules", "Be nice")]), "");
+    }
+
+    #[test]
+    fn nests_blocks_and_drops_standalone_tag_lines() {
+        let template = "Start\n{{#if section}}\nIn {{section}}\n  {{#unless current_task}}\nNothing left\n  {{/unless}}\n{{/if}}\nEnd\n";
+        assert_eq!(render(template, &[("section", "API")]), "Start\nIn API\nNothing left\nEnd");
+        assert_eq!(render(template, &[("section", "API"), ("current_task", "x")]), "Start\nIn API\nEnd");
+        assert_eq!(render(template, &[]), "Start\nEnd");
+    }
+
+    #[test]
+    fn rejects_unclosed_and_stray_tags() {
+        assert!(parse_error("{{#if section}}text").contains("'{{#if section}}' is not closed with '{{/if}}'"));
+        assert!(parse_error("{{#unless section}}a{{else}}b").contains("is not closed with '{{/unless}}'"));
+        assert!(parse_error("{{#if section}}a{{/unless}}").contains("is not closed with '{{/if}}'"));
+        assert!(parse_error("a{{/if}}").contains("unexpected '{{/if}}'"));
+        assert!(parse_error("a{{else}}b").contains("unexpected '{{else}}'"));
+        assert!(parse_error("{{section").contains("'{{' is not closed with '}}'"));
+    }
+
+    #[test]
+    fn rejects_unknown_variables_and_blocks() {
+        assert!(parse_error("{{nope}}").contains("unknown variable 'nope'"));
+        assert!(parse_error("{{#if nope}}x{{/if}}").contains("unknown variable 'nope'"));
+        assert!(parse_error("{{#each tasks}}x{{/each}}").contains("unknown block '{{#each}}'"));
+    }
+}